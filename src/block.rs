@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*! <dispatch/block.h>
+
+Dispatch block objects wrap work so it can be cancelled before it starts, waited on, or followed by a notification.
+*/
+use std::ffi::c_void;
+use std::ops::BitOr;
+use std::os::raw::{c_int, c_ulong};
+use std::panic::AssertUnwindSafe;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::block_impl::{async_block, WorkBlock};
+use crate::qos::QoS;
+use crate::queue::Unmanaged as UnmanagedQueue;
+use crate::time::{Time, TimedOut};
+
+///Like `dispatch_block_flags_t`.  Combine flags with `|`.
+#[repr(transparent)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub struct BlockFlags(c_ulong);
+impl BlockFlags {
+    ///`DISPATCH_BLOCK_BARRIER`: acts as a barrier when submitted to a concurrent queue.
+    pub const BARRIER: BlockFlags = BlockFlags(0x1);
+    ///`DISPATCH_BLOCK_DETACHED`: don't inherit execution context (activity, QoS, etc.) from the current thread.
+    pub const DETACHED: BlockFlags = BlockFlags(0x2);
+    ///`DISPATCH_BLOCK_ASSIGN_CURRENT`: assign the execution context of the current thread at creation time.
+    pub const ASSIGN_CURRENT: BlockFlags = BlockFlags(0x4);
+    ///`DISPATCH_BLOCK_NO_QOS_CLASS`: don't assign a QoS class.
+    pub const NO_QOS_CLASS: BlockFlags = BlockFlags(0x8);
+    ///`DISPATCH_BLOCK_INHERIT_QOS_CLASS`: prefer the QoS class of the queue over the block's.
+    pub const INHERIT_QOS_CLASS: BlockFlags = BlockFlags(0x10);
+    ///`DISPATCH_BLOCK_ENFORCE_QOS_CLASS`: prefer the block's QoS class over the queue's, when the block's is higher.
+    pub const ENFORCE_QOS_CLASS: BlockFlags = BlockFlags(0x20);
+    const ALL: BlockFlags = BlockFlags(0x3f);
+
+    ///The raw `dispatch_block_flags_t` bits.
+    pub const fn bits(self) -> c_ulong {
+        self.0
+    }
+    ///Creates flags from raw `dispatch_block_flags_t` bits, discarding any bits that aren't one of the flags above.
+    pub const fn from_bits_truncate(bits: c_ulong) -> BlockFlags {
+        BlockFlags(bits & Self::ALL.0)
+    }
+}
+impl BitOr for BlockFlags {
+    type Output = BlockFlags;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        BlockFlags(self.0 | rhs.0)
+    }
+}
+
+extern "C" {
+    fn dispatch_block_create(flags: c_ulong, block: *mut c_void) -> *mut c_void;
+    fn dispatch_block_create_with_qos_class(flags: c_ulong, qos_class: std::os::raw::c_uint, relative_priority: c_int, block: *mut c_void) -> *mut c_void;
+    fn dispatch_block_cancel(block: *mut c_void);
+    fn dispatch_block_testcancel(block: *mut c_void) -> isize;
+    fn dispatch_block_wait(block: *mut c_void, timeout: Time) -> isize;
+    ///notification_block is actually &AsyncBlock
+    fn dispatch_block_notify(block: *mut c_void, queue: *const UnmanagedQueue, notification_block: *mut c_void);
+    fn dispatch_async(queue: &UnmanagedQueue, block: *mut c_void);
+    fn _Block_copy(block: *const c_void) -> *mut c_void;
+    fn _Block_release(block: *const c_void);
+}
+
+/**
+A dispatch block object, created with `dispatch_block_create`.
+
+The closure runs at most once, no matter how many times the block is submitted.  If the block is cancelled before it starts, or never submitted,
+the closure is dropped along with the block.
+
+libdispatch crashes the process if a block is waited on more than once, or is both submitted more than once and waited on.
+This type tracks submissions and waits (across clones), and panics instead.
+
+Cloning the block calls `Block_copy`, and dropping it calls `Block_release`.
+*/
+#[derive(Debug)]
+pub struct DispatchBlock {
+    block: NonNull<c_void>,
+    //SUBMITTED etc., shared by clones
+    state: Arc<AtomicUsize>,
+}
+const SUBMITTED: usize = 0x1;
+const SUBMITTED_AGAIN: usize = 0x2;
+const WAITING: usize = 0x4;
+const WAITED: usize = 0x8;
+const NOTIFIED: usize = 0x10;
+unsafe impl Send for DispatchBlock {}
+unsafe impl Sync for DispatchBlock {}
+
+impl DispatchBlock {
+    ///Calls `dispatch_block_create`.
+    pub fn new<F>(flags: BlockFlags, f: F) -> Self where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = work_block(f);
+            Self::from_raw(dispatch_block_create(flags.0, &mut block as *mut _ as *mut c_void))
+        }
+    }
+    ///Calls `dispatch_block_create_with_qos_class`.
+    ///
+    /// `relative_priority` is an offset within the QoS class, from `-15` (`QOS_MIN_RELATIVE_PRIORITY`) to `0`.
+    ///
+    /// # Panics
+    /// If `relative_priority` is out of range.
+    pub fn with_qos<F>(flags: BlockFlags, qos: QoS, relative_priority: c_int, f: F) -> Self where F: FnOnce() + Send + 'static {
+        assert!((-15..=0).contains(&relative_priority), "relative_priority {} out of range -15..=0", relative_priority);
+        unsafe {
+            let mut block = work_block(f);
+            Self::from_raw(dispatch_block_create_with_qos_class(flags.0, qos.as_raw(), relative_priority, &mut block as *mut _ as *mut c_void))
+        }
+    }
+    unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        Self {
+            block: NonNull::new(ptr).expect("dispatch_block_create"),
+            state: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+    ///Atomically checks `state` with `check`, then adds `update(state)`.
+    fn transition(&self, check: impl Fn(usize), update: impl Fn(usize) -> usize) {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            check(state);
+            match self.state.compare_exchange_weak(state, state | update(state), Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+    ///Submits the block to `queue` with `dispatch_async`.
+    ///
+    /// # Panics
+    /// If the block was already submitted and has been waited on or observed with [DispatchBlock::notify], which would crash libdispatch.
+    pub fn submit(&self, queue: &UnmanagedQueue) {
+        self.transition(|state| {
+            assert!(state & SUBMITTED == 0 || state & (WAITING | WAITED) == 0, "a dispatch block may not be both submitted more than once and waited on");
+            assert!(state & SUBMITTED == 0 || state & NOTIFIED == 0, "a dispatch block may not be both submitted more than once and observed");
+        }, |state| if state & SUBMITTED == 0 { SUBMITTED } else { SUBMITTED_AGAIN });
+        unsafe{ dispatch_async(queue, self.block.as_ptr()) }
+    }
+    ///Calls `dispatch_block_cancel`.
+    ///
+    /// If the block has not started yet, it will not run.  Cancellation has no effect on a block that is already running.
+    pub fn cancel(&self) {
+        unsafe{ dispatch_block_cancel(self.block.as_ptr()) }
+    }
+    ///Calls `dispatch_block_testcancel`.  Returns whether the block has been cancelled.
+    pub fn testcancel(&self) -> bool {
+        unsafe{ dispatch_block_testcancel(self.block.as_ptr()) != 0 }
+    }
+    ///Calls `dispatch_block_wait`.
+    ///
+    /// Waits until the block has finished executing, or `timeout` passes.  After a timeout, the block may be waited on again.
+    ///
+    /// A cancelled block counts as finished once it has been submitted and dequeued (without running).  A block that is never
+    /// submitted never finishes, even if cancelled, so waiting on it lasts until `timeout`.
+    ///
+    /// # Panics
+    /// If the block was already waited on successfully (or is being waited on from another thread), or was submitted more than once,
+    /// which would crash libdispatch.
+    pub fn wait(&self, timeout: Time) -> Result<(),TimedOut> {
+        self.transition(|state| {
+            assert!(state & (WAITING | WAITED) == 0, "a dispatch block may not be waited on more than once");
+            assert!(state & SUBMITTED_AGAIN == 0, "a dispatch block may not be both submitted more than once and waited on");
+        }, |_| WAITING);
+        let result = unsafe{ dispatch_block_wait(self.block.as_ptr(), timeout) };
+        if result == 0 {
+            self.state.fetch_or(WAITED, Ordering::Release);
+            Ok(())
+        }
+        else {
+            self.state.fetch_and(!WAITING, Ordering::Release);
+            Err(TimedOut)
+        }
+    }
+    ///Calls `dispatch_block_notify`.
+    ///
+    /// Submits `f` to `queue` once the block has finished executing.  As with [DispatchBlock::wait], a cancelled block finishes once
+    /// it has been submitted and dequeued; if it's never submitted, `f` never runs.
+    ///
+    /// # Panics
+    /// If the block was submitted more than once, which would crash libdispatch.
+    pub fn notify<F>(&self, queue: &UnmanagedQueue, f: F) where F: FnOnce() + Send + 'static {
+        self.transition(|state| {
+            assert!(state & SUBMITTED_AGAIN == 0, "a dispatch block may not be both submitted more than once and observed");
+        }, |_| NOTIFIED);
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_block_notify(self.block.as_ptr(), queue, &mut block as *mut _ as *mut c_void);
+        }
+    }
+}
+impl Clone for DispatchBlock {
+    fn clone(&self) -> Self {
+        Self {
+            block: unsafe{ NonNull::new_unchecked(_Block_copy(self.block.as_ptr())) },
+            state: self.state.clone(),
+        }
+    }
+}
+impl Drop for DispatchBlock {
+    fn drop(&mut self) {
+        unsafe{ _Block_release(self.block.as_ptr()) }
+    }
+}
+
+///A block that runs `f` the first time it's invoked, and does nothing afterwards.
+///
+/// If it's never invoked, `f` is dropped when the block is disposed.
+///
+/// # Safety
+/// The caller must arrange for the block to be copied exactly once, as `f` is only dropped on dispose.
+unsafe fn work_block<F>(f: F) -> WorkBlock where F: FnOnce() + Send + 'static {
+    let environment = Mutex::new(Some(f));
+    WorkBlock::reentrant(move || {
+        let f = environment.lock().unwrap().take();
+        if let Some(f) = f {
+            //like async_block, panics can't unwind into libdispatch
+            let _ = std::panic::catch_unwind(AssertUnwindSafe(f));
+        }
+    })
+}
+
+#[test] fn dispatch_block() {
+    use std::time::Duration;
+    assert_eq!(BlockFlags::from_bits_truncate(0x101), BlockFlags::BARRIER);
+    assert_eq!((BlockFlags::BARRIER | BlockFlags::DETACHED).bits(), 0x3);
+    let queue = crate::queue::global(QoS::Default).unwrap();
+    let (sender,receiver) = std::sync::mpsc::channel();
+    let block = DispatchBlock::with_qos(BlockFlags::ENFORCE_QOS_CLASS, QoS::UserInitiated, 0, move || {
+        sender.send(()).unwrap();
+    });
+    let (notify_sender,notify_receiver) = std::sync::mpsc::channel();
+    block.notify(queue, move || {
+        notify_sender.send(()).unwrap();
+    });
+    block.submit(queue);
+    block.wait(Time::FOREVER).unwrap();
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+    assert!(notify_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+    assert!(!block.testcancel());
+    //libdispatch would crash on these, so we panic first
+    let clone = block.clone();
+    assert!(std::panic::catch_unwind(AssertUnwindSafe(|| clone.wait(Time::NOW))).is_err());
+    assert!(std::panic::catch_unwind(AssertUnwindSafe(|| block.submit(queue))).is_err());
+
+    let block = DispatchBlock::new(BlockFlags::default(), || ());
+    block.submit(queue);
+    block.submit(queue);
+    assert!(std::panic::catch_unwind(AssertUnwindSafe(|| block.notify(queue, || ()))).is_err());
+}
+
+#[test] fn cancel_block() {
+    use std::time::Duration;
+    let label = std::ffi::CStr::from_bytes_with_nul(b"dispatchr.cancel_block\0").unwrap();
+    let queue = crate::queue::Inactive::new(label, &crate::queue::QueueAttributes::serial());
+    let (sender,receiver) = std::sync::mpsc::channel::<()>();
+    let block = DispatchBlock::new(BlockFlags::default(), move || {
+        sender.send(()).unwrap();
+    });
+    block.submit(&queue);
+    block.cancel();
+    assert!(block.testcancel());
+    let _queue = queue.activate();
+    block.wait(Time::FOREVER).unwrap();
+    drop(block);
+    //the closure never ran, and has been dropped along with its sender
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Err(std::sync::mpsc::RecvTimeoutError::Disconnected));
+}
@@ -2,7 +2,8 @@
 
 //! `https://clang.llvm.org/docs/Block-ABI-Apple.html#high-level`
 
-use std::os::raw::{c_int};
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_ulong};
 use crate::data::Unmanaged;
 
 use blocksr::once_escaping;
@@ -13,6 +14,15 @@ once_escaping!(pub(crate) WriteEscapingBlock(data: Option<&Unmanaged>, error: c_
 //all arguments to this one passed in via closure
 once_escaping!(pub(crate) DropBlock() -> ());
 
+//all arguments to this one passed in via closure
+once_escaping!(pub(crate) AsyncBlock() -> ());
+
+//the work for a dispatch_block_t, which may be copied and invoked any number of times
+pub(crate) type WorkBlock = EscapingBlock;
+
+//handlers that libdispatch invokes serially, like dispatch_source event handlers
+pub(crate) type HandlerBlock = EscapingBlock;
+
 ///A block that will drop the receiver.  This can be used to transfer
 /// ownership of the receiver into dispatch.
 ///
@@ -27,3 +37,137 @@ pub(crate) unsafe fn drop_block<T: Send + 'static>(t: T) -> DropBlock {
     })
 }
 
+///A block that runs the closure, for `dispatch_async` and friends.
+///
+/// The closure is consumed (and therefore dropped) exactly once when the block executes.  If it panics,
+/// the panic is reported by the panic hook and then caught, since unwinding into libdispatch is not possible.
+///
+/// # Safety
+/// You must verify that
+//  * Block will execute exactly once:
+//      * If ObjC executes the block several times, it's UB
+//      * If ObjC executes the block less than once, it is not UB, but it will leak.
+pub(crate) unsafe fn async_block<F: FnOnce() + Send + 'static>(f: F) -> AsyncBlock {
+    AsyncBlock::new(move || {
+        //f is moved into catch_unwind, so it's dropped either by running to completion or by unwinding
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    })
+}
+
+///A block that calls `f` each time it's invoked, and drops it when the block is disposed.
+///
+/// Like [async_block], panics are caught.
+///
+/// # Safety
+/// The block must not be invoked re-entrantly, and the caller must arrange for it to be copied exactly once, as the handler is only dropped on dispose.
+pub(crate) unsafe fn handler_block<F: FnMut() + Send + 'static>(f: F) -> HandlerBlock {
+    let mut f = f;
+    HandlerBlock::nonreentrant(move || {
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&mut f));
+    })
+}
+
+///A block that calls `f` the first time it's invoked, and does nothing afterwards.  If it's never invoked, `f` is dropped when the block is disposed.
+///
+/// Like [async_block], panics are caught.
+///
+/// # Safety
+/// The block must not be invoked re-entrantly, and the caller must arrange for it to be copied exactly once, as the handler is only dropped on dispose.
+pub(crate) unsafe fn once_handler_block<F: FnOnce() + Send + 'static>(f: F) -> HandlerBlock {
+    let mut f = Some(f);
+    HandlerBlock::nonreentrant(move || {
+        if let Some(f) = f.take() {
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        }
+    })
+}
+
+extern "C" {
+    static _NSConcreteStackBlock: c_void;
+}
+
+const BLOCK_HAS_COPY_DISPOSE: c_int = 1 << 25;
+
+#[repr(C)]
+struct EscapingBlockDescriptor {
+    reserved: c_ulong,
+    size: c_ulong,
+    copy_helper: extern "C" fn(dst: *mut EscapingBlock, src: *const EscapingBlock),
+    dispose_helper: extern "C" fn(block: *mut EscapingBlock),
+}
+
+static ESCAPING_BLOCK_DESCRIPTOR: EscapingBlockDescriptor = EscapingBlockDescriptor {
+    reserved: 0,
+    size: std::mem::size_of::<EscapingBlock>() as c_ulong,
+    copy_helper: escaping_copy_helper,
+    dispose_helper: escaping_dispose_helper,
+};
+
+//The heap copy takes over the payload pointer from the stack literal, so there's nothing to do here.
+//(blocksr's `many_escaping_*` helpers print to stdout, which is why we don't use them.)
+extern "C" fn escaping_copy_helper(_dst: *mut EscapingBlock, _src: *const EscapingBlock) {}
+
+extern "C" fn escaping_dispose_helper(block: *mut EscapingBlock) {
+    unsafe { ((*block).drop_payload)((*block).payload) }
+}
+
+extern "C" fn escaping_invoke(block: *mut EscapingBlock) {
+    unsafe { ((*block).call_payload)((*block).payload) }
+}
+
+///A stack block literal that calls a boxed closure each time it's invoked, and drops it when the block is disposed.
+///
+/// The runtime copies the literal to the heap, and the heap copy owns the closure from then on.
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct EscapingBlock {
+    isa: *const c_void,
+    flags: c_int,
+    reserved: c_int,
+    invoke: extern "C" fn(*mut EscapingBlock),
+    descriptor: *const EscapingBlockDescriptor,
+    //captured variables
+    payload: *mut c_void,
+    call_payload: unsafe fn(*mut c_void),
+    drop_payload: unsafe fn(*mut c_void),
+}
+
+impl EscapingBlock {
+    fn with_payload<F>(f: F, call_payload: unsafe fn(*mut c_void)) -> Self {
+        unsafe fn drop_payload<F>(payload: *mut c_void) {
+            std::mem::drop(Box::from_raw(payload as *mut F))
+        }
+        EscapingBlock {
+            isa: unsafe { &_NSConcreteStackBlock },
+            flags: BLOCK_HAS_COPY_DISPOSE,
+            reserved: 0,
+            invoke: escaping_invoke,
+            descriptor: &ESCAPING_BLOCK_DESCRIPTOR,
+            payload: Box::into_raw(Box::new(f)) as *mut c_void,
+            call_payload,
+            drop_payload: drop_payload::<F>,
+        }
+    }
+
+    ///A block that may be invoked re-entrantly.
+    ///
+    /// # Safety
+    /// The caller must arrange for the literal to be copied exactly once, as `f` is only dropped on dispose.
+    pub(crate) unsafe fn reentrant<F: Fn() + Send + Sync + 'static>(f: F) -> Self {
+        unsafe fn call_payload<F: Fn()>(payload: *mut c_void) {
+            (*(payload as *const F))()
+        }
+        Self::with_payload(f, call_payload::<F>)
+    }
+
+    ///A block that libdispatch invokes serially.
+    ///
+    /// # Safety
+    /// The block must not be invoked re-entrantly, and the caller must arrange for the literal to be copied exactly once, as `f` is only dropped on dispose.
+    pub(crate) unsafe fn nonreentrant<F: FnMut() + Send + 'static>(f: F) -> Self {
+        unsafe fn call_payload<F: FnMut()>(payload: *mut c_void) {
+            (*(payload as *mut F))()
+        }
+        Self::with_payload(f, call_payload::<F>)
+    }
+}
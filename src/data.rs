@@ -6,6 +6,7 @@ Maps dispatch_data_t to Rust types.
 Note: If you want to map Rust memory into a dispatch data object, see [crate::external_data].
 */
 use std::ffi::c_void;
+use crate::object::Object;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -16,6 +17,7 @@ Compare with [Managed].
 pub struct Unmanaged(c_void);
 #[cfg(feature="bridge")]
 unsafe impl objr::bindings::Arguable for Unmanaged {}
+unsafe impl Object for Unmanaged {}
 impl DispatchData for &Unmanaged {
     fn as_unmanaged(&self) -> &Unmanaged {
         self
@@ -58,8 +60,9 @@ pub trait DispatchData {
 pub struct Managed(*const Unmanaged);
 impl Managed {
     pub fn retain(unmanaged: *const Unmanaged) -> Self {
-        unsafe{ dispatch_retain(unmanaged as *const _) }
-        Managed(unmanaged)
+        let managed = Managed(unmanaged);
+        managed.as_unmanaged().retain();
+        managed
     }
     pub fn empty() -> Self {
         Managed(Unmanaged::new())
@@ -77,7 +80,7 @@ impl DispatchData for Managed {
 impl Drop for Managed {
     fn drop(&mut self) {
         unsafe{
-            dispatch_release(self.0 as *const _ as *const c_void)
+            (*self.0).release()
         }
     }
 }
@@ -127,13 +130,27 @@ extern "C" {
     fn dispatch_data_create_map(data: *const Unmanaged, buffer_ptr: *mut *const c_void,
     size_ptr: *mut usize) -> *const Unmanaged;
     fn dispatch_data_create_concat(data1: *const Unmanaged, data2: *const Unmanaged) -> *const c_void;
-    pub fn dispatch_release(object: *const c_void);
-
-    pub fn dispatch_retain(object:  *const c_void);
     fn dispatch_data_create(buffer: *const c_void, size: usize, queue: *const super::queue::Unmanaged, destructor: *const c_void) -> *const Unmanaged;
     fn dispatch_data_get_size(buffer: *const Unmanaged) -> usize;
 }
 
+///Calls `dispatch_retain`.
+///
+/// # Safety
+/// `object` must be a valid dispatch object.
+#[deprecated(note = "use `dispatchr::object::Object::retain`")]
+pub unsafe fn dispatch_retain(object: *const c_void) {
+    crate::object::dispatch_retain(object)
+}
+///Calls `dispatch_release`.
+///
+/// # Safety
+/// `object` must be a valid dispatch object, and the caller must own a reference to it.
+#[deprecated(note = "use `dispatchr::object::Object::release`")]
+pub unsafe fn dispatch_release(object: *const c_void) {
+    crate::object::dispatch_release(object)
+}
+
 
 impl Contiguous {
     ///Returns the inner slice of the contiguous data
@@ -144,6 +161,6 @@ impl Contiguous {
 
 impl Drop for Contiguous {
     fn drop(&mut self) {
-        unsafe{ dispatch_release(self.object as *const _ as *const c_void) }
+        unsafe{ (*self.object).release() }
     }
 }
\ No newline at end of file
@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*!
+A Rust `Future` executor backed by dispatch queues.
+
+[spawn] polls a future on any dispatch queue.  When the future is woken, it's re-submitted to the same queue, so
+a serial queue polls its futures one at a time, and the QoS of the queue applies to the future.
+
+```no_run
+use dispatchr::executor::{spawn, block_on};
+use dispatchr::queue::global;
+use dispatchr::QoS;
+let handle = spawn(global(QoS::Utility).unwrap(), async {
+    2 + 2
+});
+assert_eq!(block_on(handle), 4);
+```
+*/
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use crate::queue::{Managed, Unmanaged as UnmanagedQueue};
+use crate::semaphore;
+use crate::time::Time;
+
+/**
+The sending half of a one-shot channel, for completing a future from a dispatch queue.
+
+Dropping the sender without sending completes the receiver with `None`.
+*/
+pub(crate) struct Sender<T>(Arc<Mutex<Shared<T>>>);
+///The receiving half of a one-shot channel.  A future for the sent value.
+pub(crate) struct Receiver<T>(Arc<Mutex<Shared<T>>>);
+struct Shared<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        value: None,
+        waker: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+    }));
+    (Sender(shared.clone()), Receiver(shared))
+}
+impl<T> Sender<T> {
+    pub(crate) fn send(self, value: T) {
+        self.0.lock().unwrap().value = Some(value);
+        //drop wakes the receiver
+    }
+    ///Whether the receiver has been dropped, so the value is no longer wanted.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().receiver_dropped
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut shared = self.0.lock().unwrap();
+            shared.sender_dropped = true;
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+impl<T> Receiver<T> {
+    fn is_finished(&self) -> bool {
+        self.0.lock().unwrap().sender_dropped
+    }
+}
+impl<T> Future for Receiver<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(value) = shared.value.take() {
+            Poll::Ready(Some(value))
+        }
+        else if shared.sender_dropped {
+            Poll::Ready(None)
+        }
+        else {
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().receiver_dropped = true;
+    }
+}
+
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+//running, and woken while running
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+struct Task {
+    queue: Managed,
+    state: AtomicU8,
+    future: Mutex<Option<Pin<Box<dyn Future<Output=()> + Send>>>>,
+}
+impl Task {
+    fn schedule(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        if state == IDLE {
+            self.submit();
+        }
+    }
+    fn submit(self: Arc<Self>) {
+        let queue = self.queue.clone();
+        queue.async_closure(move || self.run());
+    }
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::Release);
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let ready = {
+            let mut future = self.future.lock().unwrap();
+            let poll = match future.as_mut() {
+                Some(f) => std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f.as_mut().poll(&mut cx))),
+                None => Ok(Poll::Ready(())),
+            };
+            //a panicked future is finished; dropping it drops the sender, which tells the JoinHandle
+            let ready = !matches!(poll, Ok(Poll::Pending));
+            if ready {
+                *future = None;
+            }
+            ready
+        };
+        if ready {
+            self.state.store(DONE, Ordering::Release);
+        }
+        else if self.state.compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            //woken while running
+            self.state.store(SCHEDULED, Ordering::Release);
+            self.submit();
+        }
+    }
+}
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.schedule()
+    }
+}
+
+/**
+Handle to a future spawned with [spawn].  A future for its output.
+
+Dropping the handle detaches the task; it keeps running to completion.
+*/
+pub struct JoinHandle<T>(Receiver<T>);
+impl<T> JoinHandle<T> {
+    ///Whether the task has finished (or panicked), so awaiting the handle won't block.
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+}
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    ///# Panics
+    /// If the spawned future panicked.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(Some(value)) => Poll::Ready(value),
+            Poll::Ready(None) => panic!("spawned future panicked"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+impl<T> std::fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle").field("finished", &self.is_finished()).finish()
+    }
+}
+
+///Spawns `future` onto `queue`, returning a handle for its output.
+///
+/// The future is first polled on the queue (not the calling thread), and re-submitted to the queue each time it's woken.
+/// If the future panics, the panic is reported by the panic hook, and awaiting the handle panics.
+pub fn spawn<F>(queue: &UnmanagedQueue, future: F) -> JoinHandle<F::Output> where F: Future + Send + 'static, F::Output: Send + 'static {
+    let (sender, receiver) = channel();
+    let task = Arc::new(Task {
+        queue: Managed::retain(queue),
+        state: AtomicU8::new(IDLE),
+        future: Mutex::new(Some(Box::pin(async move {
+            sender.send(future.await);
+        }))),
+    });
+    task.schedule();
+    JoinHandle(receiver)
+}
+
+struct SemaphoreWaker(semaphore::Managed);
+impl Wake for SemaphoreWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.signal();
+    }
+}
+
+///Drives `future` to completion on the current thread, blocking on a semaphore while it's pending.
+///
+/// As with [crate::queue::Unmanaged::sync_ret], be careful of deadlocks when calling this on a dispatch queue that the future is waiting on.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let semaphore = Arc::new(SemaphoreWaker(semaphore::Managed::new(0)));
+    let waker = Waker::from(semaphore.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => {
+                semaphore.0.wait(Time::FOREVER);
+            }
+        }
+    }
+}
+
+///Drives `future` to completion on the main queue, draining it with [crate::queue::run_main_until].
+///
+/// This must be called from the main thread.  It's useful in `fn main` of command-line tools, and integration tests, whose futures
+/// need to hop onto [crate::queue::main].
+pub fn block_on_main<F>(future: F) -> F::Output where F: Future + Send + 'static, F::Output: Send + 'static {
+    let handle = spawn(crate::queue::main(), future);
+    crate::queue::run_main_until(|| handle.is_finished());
+    block_on(handle)
+}
+
+#[test] fn spawn_on_queue() {
+    use std::ffi::CStr;
+    let label = CStr::from_bytes_with_nul(b"dispatchr.spawn_on_queue\0").unwrap();
+    let queue = Managed::new_serial(label);
+    let inner = queue.clone();
+    let handle = spawn(&queue, async move {
+        //yield once, to exercise the waker
+        let (sender, receiver) = channel();
+        crate::queue::global(crate::QoS::Default).unwrap().async_closure(move || sender.send(23));
+        let value = receiver.await.unwrap();
+        assert!(inner.is_current());
+        value
+    });
+    assert_eq!(block_on(handle), 23);
+}
+
+#[test] fn spawn_panic() {
+    let queue = crate::queue::global(crate::QoS::Default).unwrap();
+    let handle = spawn(queue, async {
+        panic!("panic from spawn");
+    });
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| block_on(handle)));
+    assert!(result.is_err());
+}
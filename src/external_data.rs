@@ -7,7 +7,7 @@ use crate::data::{Unmanaged, DispatchData};
 use crate::queue::Unmanaged as UnmanagedQueue;
 use crate::block_impl::{drop_block};
 use std::ffi::c_void;
-use crate::data::dispatch_release;
+use crate::object::Object;
 
 pub trait HasMemory {
     fn as_slice(&self) -> &[u8];
@@ -96,7 +96,7 @@ impl ExternalMemory {
 }
 impl Drop for ExternalMemory {
     fn drop(&mut self) {
-        unsafe{ dispatch_release(self.object as *const c_void) };
+        unsafe{ (*self.object).release() };
     }
 }
 impl DispatchData for ExternalMemory {
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*! <dispatch/group.h>
+
+Groups join a set of work items, so you can wait for (or be notified of) the completion of all of them.
+*/
+use std::ffi::c_void;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use crate::block_impl::async_block;
+use crate::object::Object;
+use crate::queue::Unmanaged as UnmanagedQueue;
+use crate::time::{Time, TimedOut};
+
+extern "C" {
+    fn dispatch_group_create() -> *mut UnmanagedGroup;
+    ///block parameter is actually &AsyncBlock
+    fn dispatch_group_async(group: *const UnmanagedGroup, queue: *const UnmanagedQueue, block: *mut c_void);
+    fn dispatch_group_enter(group: *const UnmanagedGroup);
+    fn dispatch_group_leave(group: *const UnmanagedGroup);
+    fn dispatch_group_wait(group: *const UnmanagedGroup, timeout: Time) -> isize;
+    ///block parameter is actually &AsyncBlock
+    fn dispatch_group_notify(group: *const UnmanagedGroup, queue: *const UnmanagedQueue, block: *mut c_void);
+}
+
+///Unmanaged `dispatch_group_t`, performs no memory management.  Generally, you work with references of this type and/or [Group].
+#[repr(C)]
+#[derive(Debug)]
+pub struct UnmanagedGroup(c_void);
+unsafe impl Object for UnmanagedGroup {}
+
+impl UnmanagedGroup {
+    ///Calls `dispatch_group_async`.
+    ///
+    /// Submits `f` to `queue`, as part of the group.  Like [UnmanagedQueue::async_closure], `f` is dropped exactly once and panics do not unwind into libdispatch.
+    pub fn async_in<F>(&self, queue: &UnmanagedQueue, f: F) where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_group_async(self, queue, &mut block as *mut _ as *mut c_void);
+        }
+    }
+    ///Calls `dispatch_group_wait`.
+    ///
+    /// Waits until all the work in the group has finished, or `timeout` passes.
+    pub fn wait(&self, timeout: Time) -> Result<(),TimedOut> {
+        let result = unsafe{ dispatch_group_wait(self, timeout) };
+        if result == 0 {
+            Ok(())
+        }
+        else {
+            Err(TimedOut)
+        }
+    }
+    ///Calls `dispatch_group_notify`.
+    ///
+    /// Submits `f` to `queue` once all the work currently in the group has finished.  If the group is already empty, `f` is submitted immediately.
+    pub fn notify<F>(&self, queue: &UnmanagedQueue, f: F) where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_group_notify(self, queue, &mut block as *mut _ as *mut c_void);
+        }
+    }
+}
+
+/**
+Memory-managed `dispatch_group_t`.
+
+Cloning the group retains it, and dropping it releases it.
+*/
+#[derive(Debug)]
+pub struct Group(NonNull<UnmanagedGroup>);
+unsafe impl Send for Group {}
+unsafe impl Sync for Group {}
+
+impl Group {
+    ///Calls `dispatch_group_create`.
+    pub fn new() -> Self {
+        Self(NonNull::new(unsafe{ dispatch_group_create() }).expect("dispatch_group_create"))
+    }
+    ///Calls `dispatch_group_enter`, returning a guard that calls `dispatch_group_leave` when dropped.
+    ///
+    /// This is useful to track work that isn't submitted with [UnmanagedGroup::async_in], such as a completion handler.  The guard keeps the group alive,
+    /// so it can be moved into the handler.
+    pub fn enter(&self) -> Entered {
+        unsafe{ dispatch_group_enter(self.0.as_ptr()) };
+        Entered(self.clone())
+    }
+}
+impl Default for Group {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Deref for Group {
+    type Target = UnmanagedGroup;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe{ self.0.as_ref() }
+    }
+}
+impl Clone for Group {
+    fn clone(&self) -> Self {
+        self.retain();
+        Self(self.0)
+    }
+}
+impl Drop for Group {
+    fn drop(&mut self) {
+        unsafe{ self.release() }
+    }
+}
+
+///Guard returned by [Group::enter].  Calls `dispatch_group_leave` when dropped.
+#[derive(Debug)]
+#[must_use = "dropping the guard leaves the group immediately"]
+pub struct Entered(Group);
+impl Drop for Entered {
+    fn drop(&mut self) {
+        unsafe{ dispatch_group_leave(self.0.0.as_ptr()) }
+    }
+}
+
+#[test] fn group_async() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::QoS;
+    let queue = crate::queue::global(QoS::Default).unwrap();
+    let group = Group::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let counter = counter.clone();
+        group.async_in(queue, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    group.wait(Time::FOREVER).unwrap();
+    assert_eq!(counter.load(Ordering::Relaxed), 10);
+}
+
+#[test] fn group_enter() {
+    use crate::QoS;
+    let queue = crate::queue::global(QoS::Default).unwrap();
+    let group = Group::new();
+    let entered = group.enter();
+    assert_eq!(group.wait(Time::NOW), Err(TimedOut));
+
+    let (sender,receiver) = std::sync::mpsc::channel();
+    group.notify(queue, move || {
+        sender.send(()).unwrap();
+    });
+    queue.async_closure(move || {
+        drop(entered);
+    });
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+    assert_eq!(group.wait(Time::NOW), Ok(()));
+}
@@ -9,7 +9,8 @@ use std::ffi::{c_void, CStr};
 use std::ops::Deref;
 use std::ptr::NonNull;
 use libc::{mode_t, off_t, size_t};
-use crate::data::{Unmanaged, DispatchData, dispatch_release, dispatch_retain};
+use crate::data::{Unmanaged, DispatchData};
+use crate::object::Object;
 use crate::block_impl::{WriteEscapingBlock};
 
 ///dispatch type for file descriptor
@@ -35,6 +36,7 @@ impl dispatch_io_type_t {
 pub struct UnmanagedIO(c_void);
 unsafe impl Send for UnmanagedIO {}
 unsafe impl Sync for UnmanagedIO {}
+unsafe impl Object for UnmanagedIO {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
@@ -117,7 +119,7 @@ Therefore, there is no need to call .close().
 pub struct IO(NonNull<UnmanagedIO>);
 impl Clone for IO {
     fn clone(&self) -> Self {
-        unsafe{dispatch_retain(self.0.as_ptr() as *const c_void)};
+        self.retain();
         Self(self.0)
     }
 }
@@ -165,7 +167,7 @@ impl Drop for IO {
     fn drop(&mut self) {
         self.close(dispatch_io_close_flags_t::STOP);
         unsafe{
-            dispatch_release(self.0.as_ptr() as *const c_void)
+            self.release()
         }
     }
 }
@@ -238,7 +240,7 @@ impl Drop for IO {
     let as_ref = unsafe{&*f};
     as_ref.close(dispatch_io_close_flags_t::default());
     unsafe{
-        dispatch_release(as_ref as *const _ as *const c_void);
+        as_ref.release();
     }
 
     let p = IO::new_with_path(dispatch_io_type_t::STREAM, &c_path, 0, 0, queue);
@@ -19,12 +19,17 @@ for Apple platform features that mimic code from first-party compilers.  Distinc
 # Status
 dispatchr covers large but incomplete portions of the libdispatch API.
 
-* global queues, dispatch_sync
+* global and main queues (including `dispatch_main`), serial and concurrent queues, workloops, dispatch_sync, dispatch_async_and_wait, dispatch_async, dispatch_after, dispatch_apply
 * qos
+* object: retain/release, suspend/resume guards, inactive type-states, contexts with finalizers
 * popular portions of io: `dispatch_read`, `dispatch_write`, `dispatch_io_create_with_path`
 * data
 * semaphore
-* source (timers only)
+* group
+* once
+* dispatch block objects
+* a `Future` executor backed by dispatch queues
+* source (timers, custom data, file descriptor readiness, signals, and processes)
 
 */
 
@@ -39,5 +44,11 @@ pub mod external_data;
 pub mod semaphore;
 pub mod time;
 pub mod source;
+pub mod group;
+pub mod once;
+pub mod block;
+pub mod workloop;
+pub mod object;
+pub mod executor;
 
 pub use qos::QoS;
@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*! <dispatch/object.h>
+
+Operations common to all dispatch objects.
+*/
+use std::any::TypeId;
+use std::ffi::c_void;
+use std::ops::Deref;
+use std::cell::UnsafeCell;
+
+extern "C" {
+    pub(crate) fn dispatch_retain(object: *const c_void);
+    pub(crate) fn dispatch_release(object: *const c_void);
+    pub(crate) fn dispatch_suspend(object: *const c_void);
+    pub(crate) fn dispatch_resume(object: *const c_void);
+    fn dispatch_activate(object: *const c_void);
+    fn dispatch_set_context(object: *const c_void, context: *mut c_void);
+    fn dispatch_get_context(object: *const c_void) -> *mut c_void;
+    fn dispatch_set_finalizer_f(object: *const c_void, finalizer: extern "C" fn(*mut c_void));
+}
+
+///Serializes [Object::set_context], so that a context and its finalizer are always installed as a pair.
+static SET_CONTEXT_LOCK: Lock = Lock::new();
+
+/**
+A dispatch object, like `dispatch_object_t`.
+
+This is implemented for the unmanaged types (queue, source, IO, data, semaphore, group), and generally
+reached through the `Deref` of the managed ones.
+
+Suspension only applies to queues and sources; for other objects, libdispatch ignores it.  Activation is deliberately not
+offered here, but by [Inactive], since some configuration crashes libdispatch once an object is active.
+
+# Safety
+Implementors must be unmanaged dispatch object types, such that a reference to `Self` is a valid `dispatch_object_t`.
+*/
+pub unsafe trait Object: Sized {
+    ///Returns the `dispatch_object_t` for this object.
+    fn as_object_ptr(&self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+    ///Calls `dispatch_retain`.
+    ///
+    /// Each call must be balanced by a call to [Object::release], or the object is leaked.
+    fn retain(&self) {
+        unsafe{ dispatch_retain(self.as_object_ptr()) }
+    }
+    ///Calls `dispatch_release`.
+    ///
+    /// # Safety
+    /// The caller must own a reference to the object (from its creation or [Object::retain]), which is given up.  If it was the
+    /// last reference, the object is deallocated and must not be used again.
+    unsafe fn release(&self) {
+        dispatch_release(self.as_object_ptr())
+    }
+    ///Calls `dispatch_suspend`, returning a guard that calls `dispatch_resume` when dropped.
+    ///
+    /// Suspending a queue or source prevents it from invoking any new work or handlers, although work already running continues.
+    /// Since the guard balances the calls, the suspension count cannot underflow (which crashes libdispatch).
+    fn suspend(&self) -> Suspended<'_, Self> {
+        unsafe{ dispatch_suspend(self.as_object_ptr()) }
+        Suspended(self)
+    }
+    ///Calls `dispatch_set_context`, and `dispatch_set_finalizer_f` with a finalizer that drops the context when the object is deallocated.
+    ///
+    /// The context can be found later with [Object::context], or in a handler that receives the context pointer (like
+    /// [crate::source::Unmanaged::set_event_handler_f]) with [context_from_raw].
+    ///
+    /// Global queues don't support contexts; for them, the context is dropped immediately.
+    ///
+    /// # Panics
+    /// If the object already has a context.  Replacing a context could free it out from under a reference returned by [Object::context].
+    fn set_context<T: Send + 'static>(&self, context: Box<T>) {
+        extern "C" fn finalizer<T>(context: *mut c_void) {
+            unsafe{ drop(Box::from_raw(context as *mut Context<T>)) }
+        }
+        let context = Box::into_raw(Box::new(Context {
+            type_id: TypeId::of::<T>(),
+            value: context,
+        }));
+        SET_CONTEXT_LOCK.with(|| unsafe {
+            assert!(dispatch_get_context(self.as_object_ptr()).is_null(), "object already has a context");
+            dispatch_set_context(self.as_object_ptr(), context as *mut c_void);
+            if dispatch_get_context(self.as_object_ptr()) == context as *mut c_void {
+                dispatch_set_finalizer_f(self.as_object_ptr(), finalizer::<T>);
+            }
+            else {
+                //libdispatch ignored us
+                drop(Box::from_raw(context));
+            }
+        });
+    }
+    ///Calls `dispatch_get_context`, returning the context installed by [Object::set_context] if it has type `T`.
+    ///
+    /// # Safety
+    /// The object's context must be null, or installed by [Object::set_context].  A context set by foreign code (or by
+    /// calling `dispatch_set_context` directly) has some other layout, and can't be told apart.
+    unsafe fn context<T: Sync + 'static>(&self) -> Option<&T> {
+        //contexts are never replaced, and live as long as the object
+        context_from_raw(dispatch_get_context(self.as_object_ptr()))
+    }
+}
+
+///Calls `dispatch_activate`.  Only [Inactive] calls this, as the last step of configuration.
+pub(crate) fn activate<O: Object>(object: &O) {
+    unsafe{ dispatch_activate(object.as_object_ptr()) }
+}
+
+/**
+An object that was created inactive, and can still be configured.
+
+libdispatch forbids some configuration, like changing the target queue, once an object has been activated.  So that configuration
+is only offered on this type-state, such as [crate::queue::Inactive::set_target_queue], until [Inactive::activate] returns the active object.
+
+Work may be submitted, and handlers installed, before activation (via `Deref`), but nothing runs until then.  If this type is dropped
+without activating it, the object is activated first, since libdispatch crashes when an inactive object is released.
+*/
+#[derive(Debug)]
+pub struct Inactive<T>(Option<T>) where T: Deref, T::Target: Object;
+impl<T> Inactive<T> where T: Deref, T::Target: Object {
+    ///Wraps an object that was just created inactive.
+    pub(crate) fn from_inactive(object: T) -> Self {
+        Inactive(Some(object))
+    }
+    ///Calls `dispatch_activate`, returning the active object.
+    pub fn activate(mut self) -> T {
+        let object = self.0.take().unwrap();
+        activate(&*object);
+        object
+    }
+}
+impl<T> Deref for Inactive<T> where T: Deref, T::Target: Object {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().unwrap()
+    }
+}
+impl<T> Drop for Inactive<T> where T: Deref, T::Target: Object {
+    fn drop(&mut self) {
+        if let Some(object) = self.0.take() {
+            activate(&*object);
+        }
+    }
+}
+
+///Layout of the contexts installed by [Object::set_context].
+#[repr(C)]
+struct Context<T> {
+    //first, so it can be read without knowing T
+    type_id: TypeId,
+    value: Box<T>,
+}
+
+///Recovers a context installed by [Object::set_context] from the raw pointer passed to a handler, if it has type `T`.
+///
+/// ```no_run
+/// use std::ffi::c_void;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use dispatchr::object::context_from_raw;
+/// extern "C" fn handler(context: *mut c_void) {
+///     let count: &AtomicUsize = unsafe{ context_from_raw(context) }.unwrap();
+///     count.fetch_add(1, Ordering::Relaxed);
+/// }
+/// ```
+///
+/// # Safety
+/// `context` must be null, or a context pointer installed by [Object::set_context] on an object that lives for `'a`.
+pub unsafe fn context_from_raw<'a, T: Sync + 'static>(context: *mut c_void) -> Option<&'a T> {
+    if context.is_null() {
+        return None;
+    }
+    if *(context as *const TypeId) != TypeId::of::<T>() {
+        return None;
+    }
+    Some(&*(*(context as *const Context<T>)).value)
+}
+
+///A lock for short critical sections, which can be initialized in a `static`.
+///
+/// This is `os_unfair_lock` on Apple platforms, and a `pthread_mutex_t` elsewhere.  Unlike a spinlock, waiters block in the kernel,
+/// which on Darwin lets it resolve QoS priority inversions.  It must not be moved once used, which `static` storage guarantees.
+pub(crate) struct Lock(UnsafeCell<RawLock>);
+#[cfg(target_vendor = "apple")]
+type RawLock = libc::os_unfair_lock;
+#[cfg(target_vendor = "apple")]
+const RAW_LOCK_INIT: RawLock = libc::OS_UNFAIR_LOCK_INIT;
+#[cfg(target_vendor = "apple")]
+unsafe fn raw_lock(lock: *mut RawLock) { libc::os_unfair_lock_lock(lock) }
+#[cfg(target_vendor = "apple")]
+unsafe fn raw_unlock(lock: *mut RawLock) { libc::os_unfair_lock_unlock(lock) }
+#[cfg(not(target_vendor = "apple"))]
+type RawLock = libc::pthread_mutex_t;
+#[cfg(not(target_vendor = "apple"))]
+const RAW_LOCK_INIT: RawLock = libc::PTHREAD_MUTEX_INITIALIZER;
+#[cfg(not(target_vendor = "apple"))]
+unsafe fn raw_lock(lock: *mut RawLock) { libc::pthread_mutex_lock(lock); }
+#[cfg(not(target_vendor = "apple"))]
+unsafe fn raw_unlock(lock: *mut RawLock) { libc::pthread_mutex_unlock(lock); }
+unsafe impl Send for Lock {}
+unsafe impl Sync for Lock {}
+impl Lock {
+    pub(crate) const fn new() -> Self {
+        Lock(UnsafeCell::new(RAW_LOCK_INIT))
+    }
+    pub(crate) fn with<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Unlock<'a>(&'a Lock);
+        impl Drop for Unlock<'_> {
+            fn drop(&mut self) {
+                unsafe{ raw_unlock(self.0.0.get()) }
+            }
+        }
+        unsafe{ raw_lock(self.0.get()) }
+        //unlocks even if f panics
+        let _unlock = Unlock(self);
+        f()
+    }
+}
+impl std::fmt::Debug for Lock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Lock")
+    }
+}
+
+///Guard returned by [Object::suspend].  Calls `dispatch_resume` when dropped.
+#[derive(Debug)]
+#[must_use = "dropping the guard resumes the object immediately"]
+pub struct Suspended<'a, O: Object>(&'a O);
+impl<'a, O: Object> Drop for Suspended<'a, O> {
+    fn drop(&mut self) {
+        unsafe{ dispatch_resume(self.0.as_object_ptr()) }
+    }
+}
+
+#[test] fn suspend_queue() {
+    use std::ffi::CStr;
+    use crate::queue::Managed;
+    let label = CStr::from_bytes_with_nul(b"dispatchr.suspend_queue\0").unwrap();
+    let queue = Managed::new_serial(label);
+    let (sender,receiver) = std::sync::mpsc::channel();
+    let suspended = queue.suspend();
+    queue.async_closure(move || {
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+    drop(suspended);
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+}
+
+#[test] fn context() {
+    use std::ffi::CStr;
+    use crate::queue::Managed;
+    struct Tracked(std::sync::mpsc::Sender<()>);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.send(()).unwrap();
+        }
+    }
+    let label = CStr::from_bytes_with_nul(b"dispatchr.context\0").unwrap();
+    let queue = Managed::new_serial(label);
+    let (sender,receiver) = std::sync::mpsc::channel();
+    queue.set_context(Box::new((Tracked(sender), 23_u8)));
+    assert_eq!(unsafe{ queue.context::<(Tracked, u8)>() }.unwrap().1, 23);
+    assert!(unsafe{ queue.context::<u8>() }.is_none());
+    drop(queue);
+    //finalizer runs on the target queue after the last release
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+}
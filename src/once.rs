@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*! <dispatch/once.h>
+
+[OncePredicate] is layout-compatible with `dispatch_once_t`, so a predicate can be shared with C or ObjC code.
+[Lazy] builds lazy initialization on top of it.
+*/
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+extern "C" {
+    fn dispatch_once_f(predicate: *mut isize, context: *mut c_void, function: extern "C" fn(*mut c_void));
+}
+
+///Value of a predicate once its function has run.  The inline fast path in the header relies on this as well.
+const DISPATCH_ONCE_DONE: isize = !0;
+
+/**
+Layout-compatible with `dispatch_once_t`.
+
+Predicates must have static or zero-initialized storage, and must not be moved once used.  Generally, you declare them in a `static`.
+
+```no_run
+use dispatchr::once::OncePredicate;
+static PREDICATE: OncePredicate = OncePredicate::new();
+PREDICATE.call_once(|| println!("runs once"));
+```
+*/
+#[repr(C)]
+#[derive(Debug)]
+pub struct OncePredicate(AtomicIsize);
+
+impl OncePredicate {
+    ///Creates a new, zero-initialized predicate.
+    pub const fn new() -> Self {
+        OncePredicate(AtomicIsize::new(0))
+    }
+    ///Uses a predicate that lives elsewhere, such as a `static dispatch_once_t` in C.
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid `dispatch_once_t` for the lifetime `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *mut isize) -> &'a OncePredicate {
+        &*(ptr as *const OncePredicate)
+    }
+    ///Returns a pointer to the underlying `dispatch_once_t`, for passing to C.
+    pub fn as_ptr(&self) -> *mut isize {
+        //AtomicIsize has the same in-memory representation as isize
+        &self.0 as *const AtomicIsize as *mut isize
+    }
+    ///Whether some function has already run with this predicate, on either side of the language boundary.
+    pub fn is_completed(&self) -> bool {
+        self.0.load(Ordering::Acquire) == DISPATCH_ONCE_DONE
+    }
+    ///Calls `dispatch_once_f`.
+    ///
+    /// Runs `f` if no function has run with this predicate yet.  Other callers block until `f` finishes.
+    ///
+    /// If `f` panics, the predicate is still marked completed, and the panic resumes on the calling thread.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.is_completed() {
+            return;
+        }
+        struct Context<F> {
+            f: Option<F>,
+            panic: Option<Box<dyn Any + Send>>,
+        }
+        extern "C" fn function<F: FnOnce()>(context: *mut c_void) {
+            //valid for the duration of dispatch_once_f
+            let context = unsafe{ &mut *(context as *mut Context<F>) };
+            let f = context.f.take().unwrap();
+            if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(f)) {
+                context.panic = Some(payload);
+            }
+        }
+        let mut context = Context {
+            f: Some(f),
+            panic: None,
+        };
+        unsafe {
+            dispatch_once_f(self.as_ptr(), &mut context as *mut _ as *mut c_void, function::<F>);
+        }
+        if let Some(payload) = context.panic {
+            std::panic::resume_unwind(payload);
+        }
+    }
+}
+impl Default for OncePredicate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+A value initialized on first access, with [OncePredicate].
+
+```no_run
+use dispatchr::once::Lazy;
+static CONFIG: Lazy<String> = Lazy::new(|| std::env::var("CONFIG").unwrap_or_default());
+println!("{}", *CONFIG);
+```
+*/
+pub struct Lazy<T, F = fn() -> T> {
+    predicate: OncePredicate,
+    init: UnsafeCell<Option<F>>,
+    value: UnsafeCell<Option<T>>,
+}
+//init and value are only written inside call_once, which is exclusive; afterwards value is only read.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T,F> {}
+
+impl<T,F> Lazy<T,F> {
+    ///Creates a new lazy value, which will be initialized with `init` on first access.
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            predicate: OncePredicate::new(),
+            init: UnsafeCell::new(Some(init)),
+            value: UnsafeCell::new(None),
+        }
+    }
+}
+impl<T, F: FnOnce() -> T> Lazy<T,F> {
+    ///Initializes the value if needed, and returns a reference to it.
+    ///
+    /// # Panics
+    /// If the initializer panics, either now or on a previous access.
+    pub fn force(this: &Self) -> &T {
+        this.predicate.call_once(|| {
+            let init = unsafe{ (*this.init.get()).take() }.unwrap();
+            let value = init();
+            unsafe{ *this.value.get() = Some(value) };
+        });
+        unsafe{ (*this.value.get()).as_ref() }.expect("Lazy instance has previously been poisoned")
+    }
+}
+impl<T, F: FnOnce() -> T> Deref for Lazy<T,F> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        Lazy::force(self)
+    }
+}
+impl<T: std::fmt::Debug, F> std::fmt::Debug for Lazy<T,F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.predicate.is_completed() {
+            f.debug_tuple("Lazy").field(unsafe{ &*self.value.get() }).finish()
+        }
+        else {
+            f.write_str("Lazy(<uninit>)")
+        }
+    }
+}
+
+#[test] fn call_once() {
+    static PREDICATE: OncePredicate = OncePredicate::new();
+    let mut count = 0;
+    PREDICATE.call_once(|| count += 1);
+    PREDICATE.call_once(|| count += 1);
+    assert_eq!(count, 1);
+    assert!(PREDICATE.is_completed());
+}
+
+#[test] fn lazy() {
+    use std::sync::atomic::AtomicUsize;
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static LAZY: Lazy<String> = Lazy::new(|| {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        "hello".to_string()
+    });
+    let threads: Vec<_> = (0..4).map(|_| std::thread::spawn(|| LAZY.len())).collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), 5);
+    }
+    assert_eq!(*LAZY, "hello");
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+}
@@ -6,6 +6,7 @@ use std::os::raw::c_uint;
 ///
 // --
 // Note that this isn't bridged to the C type, you must call [Self::as_raw()] instead.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum QoS {
     UserInteractive,
     UserInitiated,
@@ -26,6 +27,17 @@ impl QoS {
             QoS::Unspecified => {0x00}
         }
     }
+    ///Converts a `qos_class_t`.  Unknown values are treated as [QoS::Unspecified].
+    pub(crate) fn from_raw(raw: c_uint) -> QoS {
+        match raw {
+            0x21 => QoS::UserInteractive,
+            0x19 => QoS::UserInitiated,
+            0x15 => QoS::Default,
+            0x11 => QoS::Utility,
+            0x09 => QoS::Background,
+            _ => QoS::Unspecified,
+        }
+    }
 }
 
 impl From<priority::Priority> for QoS {
@@ -3,9 +3,20 @@
 /*! <dispatch/queue.h> */
 use std::ffi::c_void;
 use crate::qos::QoS;
-use std::os::raw::c_uint;
+use std::os::raw::{c_int, c_uint, c_ulong};
 use std::mem::MaybeUninit;
 use std::pin::Pin;
+use std::ops::Deref;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use crate::object::{Object, Lock};
+use crate::block_impl::async_block;
+use crate::time::Time;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::future::Future;
 
 
 #[repr(C)]
@@ -16,6 +27,7 @@ Often used as the currency type for dispatch queues in case we don't care about
 
  */
 pub struct Unmanaged(c_void);
+unsafe impl Object for Unmanaged {}
 blocksr::once_noescape!(pub DispatchSyncBlock() -> ());
 
 impl Unmanaged {
@@ -35,14 +47,227 @@ impl Unmanaged {
     ///
     /// If you don't need a return value, the underlying [Unmanaged::sync] method is faster.
     pub fn sync_ret<F,R>(&self, f: F) -> R where F: FnOnce() -> R + Send, R: Send {
+        self.noescape_ret(f, dispatch_sync)
+    }
+
+    ///dispatch_barrier_sync, block version.  You pass an instance of `DispatchSyncBlock` in here.
+    ///
+    /// On a concurrent queue created with [QueueAttributes::concurrent], the block waits for all previously-submitted work
+    /// to finish, and executes by itself.  On serial and global queues, this is equivalent to [Unmanaged::sync].
+    pub fn barrier_sync<F>(&self, block: &DispatchSyncBlock<F>) {
+        unsafe {
+            dispatch_barrier_sync(self, block as *const _ as *const c_void);
+        }
+    }
+    ///dispatch_barrier_sync, closure version, passing through a returned value.
+    ///
+    /// This is the 'writer' half of a reader-writer pattern, where readers use [Unmanaged::sync_ret] on the same concurrent queue.
+    /// The same deadlock caveats apply as for [Unmanaged::sync_ret].
+    ///
+    /// If you don't need a return value, the underlying [Unmanaged::barrier_sync] method is faster.
+    pub fn barrier_sync_ret<F,R>(&self, f: F) -> R where F: FnOnce() -> R + Send, R: Send {
+        self.noescape_ret(f, dispatch_barrier_sync)
+    }
+
+    ///dispatch_async_and_wait, block version.  You pass an instance of `DispatchSyncBlock` in here.
+    ///
+    /// Like [Unmanaged::sync], this waits for the block to finish.  Unlike `dispatch_sync`, which runs the block on the calling thread,
+    /// the block runs in the queue's own execution context (its thread, QoS, and autorelease pool), and the calling thread just blocks.
+    /// When the queue is idle, libdispatch may still run the block on the calling thread as an optimization, but it never brings up a new thread.
+    pub fn async_and_wait<F>(&self, block: &DispatchSyncBlock<F>) {
+        unsafe {
+            dispatch_async_and_wait(self, block as *const _ as *const c_void);
+        }
+    }
+    ///dispatch_async_and_wait, closure version, passing through a returned value.
+    ///
+    /// The same deadlock caveats apply as for [Unmanaged::sync_ret]: waiting on the queue you are already running on
+    /// (or on a serial queue it targets) deadlocks.  Consider [Unmanaged::assert_not_current].
+    ///
+    /// If you don't need a return value, the underlying [Unmanaged::async_and_wait] method is faster.
+    pub fn async_and_wait_ret<F,R>(&self, f: F) -> R where F: FnOnce() -> R + Send, R: Send {
+        self.noescape_ret(f, dispatch_async_and_wait)
+    }
+
+    ///Submits `f` as a noescape block with `submit`, which must execute it exactly once before returning.
+    fn noescape_ret<F,R>(&self, f: F, submit: unsafe extern "C" fn(&Unmanaged, *const c_void)) -> R where F: FnOnce() -> R + Send, R: Send {
         let mut block_value = MaybeUninit::uninit();
         let mut return_value = MaybeUninit::uninit();
         let block_value = unsafe{ Pin::new_unchecked(&mut block_value) };
         let block_value = unsafe{ DispatchSyncBlock::new(block_value, || {
             return_value.write(f());
         })};
-        self.sync(&block_value);
-        unsafe{ return_value.assume_init() }
+        unsafe {
+            submit(self, &*block_value as *const _ as *const c_void);
+            return_value.assume_init()
+        }
+    }
+
+    ///dispatch_async, closure version.
+    ///
+    /// The closure is moved into an escaping block and dropped exactly once, after it runs.  If the closure panics, the panic
+    /// is reported by the panic hook, but it does not unwind into libdispatch; the queue continues with the next item.
+    pub fn async_closure<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_async(self, &mut block as *mut _ as *mut c_void);
+        }
+    }
+
+    ///dispatch_barrier_async, closure version.
+    ///
+    /// On a concurrent queue created with [QueueAttributes::concurrent], the closure waits for all previously-submitted work
+    /// to finish, and executes by itself.  On serial and global queues, this is equivalent to [Unmanaged::async_closure].
+    pub fn barrier_async<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_barrier_async(self, &mut block as *mut _ as *mut c_void);
+        }
+    }
+
+    ///dispatch_after, closure version.
+    ///
+    /// Submits `f` to the queue at (or shortly after) `when`.  For a delay from now, use [Time::after].
+    ///
+    /// Passing [Time::FOREVER] is not useful, as the closure will never run, and it will be leaked.
+    pub fn after<F>(&self, when: Time, f: F) where F: FnOnce() + Send + 'static {
+        unsafe {
+            let mut block = async_block(f);
+            dispatch_after(when, self, &mut block as *mut _ as *mut c_void);
+        }
+    }
+
+    ///dispatch_apply, closure version.
+    ///
+    /// Calls `f` once for each index in `0..iterations`, possibly in parallel, and returns when all iterations are done.
+    /// Since this is synchronous, `f` may borrow from the caller's stack.  Usually you call this on a global queue.
+    ///
+    /// If any iteration panics, the remaining iterations still run; afterwards the (first) panic resumes on the calling thread.
+    pub fn apply<F>(&self, iterations: usize, f: F) where F: Fn(usize) + Sync {
+        struct Context<'a, F> {
+            f: &'a F,
+            panic: Mutex<Option<Box<dyn Any + Send>>>,
+        }
+        extern "C" fn work<F: Fn(usize) + Sync>(context: *mut c_void, index: usize) {
+            //valid for the duration of dispatch_apply_f
+            let context = unsafe{ &*(context as *const Context<F>) };
+            if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(|| (context.f)(index))) {
+                context.panic.lock().unwrap().get_or_insert(payload);
+            }
+        }
+        let context = Context {
+            f: &f,
+            panic: Mutex::new(None),
+        };
+        unsafe {
+            dispatch_apply_f(iterations, self, &context as *const _ as *mut c_void, work::<F>);
+        }
+        if let Some(payload) = context.panic.into_inner().unwrap() {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    ///Parallel version of [slice::chunks_mut] built on [Unmanaged::apply].
+    ///
+    /// Calls `f` with the index and contents of each chunk of `slice`.  Each chunk is `chunk_size` long, except possibly the last one.
+    ///
+    /// # Panics
+    /// If `chunk_size` is 0.
+    pub fn apply_chunks_mut<T,F>(&self, slice: &mut [T], chunk_size: usize, f: F) where T: Send, F: Fn(usize, &mut [T]) + Sync {
+        assert!(chunk_size != 0, "chunk_size must be non-zero");
+        struct SlicePtr<T>(*mut T);
+        impl<T> SlicePtr<T> {
+            fn get(&self) -> *mut T { self.0 }
+        }
+        //chunks are disjoint, so this is like sending each &mut [T] to another thread
+        unsafe impl<T: Send> Sync for SlicePtr<T> {}
+
+        let len = slice.len();
+        let ptr = SlicePtr(slice.as_mut_ptr());
+        //avoids overflow when chunk_size is near usize::MAX
+        let chunks = len / chunk_size + (len % chunk_size != 0) as usize;
+        self.apply(chunks, |index| {
+            let start = index * chunk_size;
+            let chunk_len = std::cmp::min(chunk_size, len - start);
+            let chunk = unsafe{ std::slice::from_raw_parts_mut(ptr.get().add(start), chunk_len) };
+            f(index, chunk)
+        });
+    }
+
+    ///Calls `dispatch_queue_get_label`.  Queues created without a label return an empty string.
+    pub fn label(&self) -> &CStr {
+        let label = unsafe{ dispatch_queue_get_label(self) };
+        if label.is_null() {
+            Default::default()
+        }
+        else {
+            //valid for the lifetime of the queue
+            unsafe{ CStr::from_ptr(label) }
+        }
+    }
+    ///Calls `dispatch_queue_get_qos_class`, returning the QoS and relative priority the queue was created with.
+    ///
+    /// Queues created without a QoS, such as the main queue, return [QoS::Unspecified].
+    pub fn qos(&self) -> (QoS, c_int) {
+        let mut relative_priority = 0;
+        let qos = unsafe{ dispatch_queue_get_qos_class(self, &mut relative_priority) };
+        (QoS::from_raw(qos), relative_priority)
+    }
+
+    ///Calls `dispatch_assert_queue`.
+    ///
+    /// Verifies that the current work is running on this queue, or on a queue that targets it.  Otherwise, libdispatch
+    /// crashes the process with a diagnostic; this is not a Rust panic and cannot be caught.
+    pub fn assert_current(&self) {
+        unsafe{ dispatch_assert_queue(self) }
+    }
+    ///Calls `dispatch_assert_queue_not`.
+    ///
+    /// Verifies that the current work is *not* running on this queue, such as before calling [Unmanaged::sync_ret] on it.
+    /// Otherwise, libdispatch crashes the process.
+    pub fn assert_not_current(&self) {
+        unsafe{ dispatch_assert_queue_not(self) }
+    }
+    ///Calls `dispatch_assert_queue_barrier`.
+    ///
+    /// Verifies that the current work is running on this queue as a barrier (or on a serial queue), such as inside [Unmanaged::barrier_async].
+    /// Otherwise, libdispatch crashes the process.
+    pub fn assert_barrier(&self) {
+        unsafe{ dispatch_assert_queue_barrier(self) }
+    }
+    ///Non-crashing variant of [Unmanaged::assert_current].  Returns whether the current work is running on this queue, or on a queue that targets it.
+    ///
+    /// This is intended for `debug_assert!(queue.is_current())`.  It only recognizes queues this crate tagged when they were created
+    /// (by [Managed::new] and [Inactive::new]) and the [main] queue.  For any other queue, such as global queues, workloops, or
+    /// queues created by foreign code, it returns `false`.
+    pub fn is_current(&self) -> bool {
+        let key = self as *const Self as *const c_void;
+        std::ptr::eq(unsafe{ dispatch_get_specific(key) }, key)
+    }
+
+    ///Runs `f` on this queue from async code, returning a future for its result.
+    ///
+    /// This is the async equivalent of [Unmanaged::sync_ret], for hopping onto a particular queue (such as [main] for UI work)
+    /// without blocking a thread.  `f` is submitted with an escaping block when the future is first polled.
+    ///
+    /// # Cancellation
+    /// If the future is dropped before it's polled, `f` is never submitted.  If it's dropped after submission but before
+    /// `f` starts, `f` is dropped on the queue without running.  If it's dropped while `f` is running, `f` runs to completion
+    /// and the result is dropped.
+    ///
+    /// # Panics
+    /// The future panics if `f` panics.
+    pub fn run<F,R>(&self, f: F) -> impl Future<Output=R> where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+        let queue = Managed::retain(self);
+        async move {
+            let (sender, receiver) = crate::executor::channel();
+            queue.async_closure(move || {
+                if !sender.is_cancelled() {
+                    sender.send(f());
+                }
+            });
+            receiver.await.expect("closure panicked")
+        }
     }
 
     pub fn async_f(&self, context: *const c_void, work: extern "C" fn (*const c_void)) {
@@ -51,11 +276,327 @@ impl Unmanaged {
         }
     }
 }
+
+///Like `dispatch_autorelease_frequency_t`.  Controls how often the queue drains an autorelease pool around its work.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum AutoreleaseFrequency {
+    ///Inherit the behavior of the target queue.  This is the default.
+    Inherit,
+    ///Push and pop an autorelease pool around each work item.
+    WorkItem,
+    ///Never set up an autorelease pool.
+    Never,
+}
+impl AutoreleaseFrequency {
+    pub(crate) fn as_raw(&self) -> c_ulong {
+        match self {
+            AutoreleaseFrequency::Inherit => {0}
+            AutoreleaseFrequency::WorkItem => {1}
+            AutoreleaseFrequency::Never => {2}
+        }
+    }
+}
+
+/**
+Builder for `dispatch_queue_attr_t`, consumed by [Managed::new] and [Inactive::new].
+
+```no_run
+use dispatchr::queue::{QueueAttributes, Inactive};
+use dispatchr::QoS;
+let attributes = QueueAttributes::serial().qos(QoS::Utility, -1);
+let queue = Inactive::new(std::ffi::CStr::from_bytes_with_nul(b"com.example.queue\0").unwrap(), &attributes);
+//configure the queue, then...
+let queue = queue.activate();
+```
+*/
+#[derive(Clone,Copy,Debug)]
+pub struct QueueAttributes {
+    concurrent: bool,
+    qos: Option<(QoS, c_int)>,
+    autorelease_frequency: Option<AutoreleaseFrequency>,
+    initially_inactive: bool,
+}
+impl QueueAttributes {
+    ///Attributes for a serial queue, like `DISPATCH_QUEUE_SERIAL`.
+    pub fn serial() -> Self {
+        QueueAttributes {
+            concurrent: false,
+            qos: None,
+            autorelease_frequency: None,
+            initially_inactive: false,
+        }
+    }
+    ///Attributes for a concurrent queue, like `DISPATCH_QUEUE_CONCURRENT`.
+    pub fn concurrent() -> Self {
+        QueueAttributes {
+            concurrent: true,
+            ..Self::serial()
+        }
+    }
+    ///Applies `dispatch_queue_attr_make_with_qos_class`.
+    ///
+    /// `relative_priority` is an offset within the QoS class, from `-15` (`QOS_MIN_RELATIVE_PRIORITY`) to `0`.
+    ///
+    /// # Panics
+    /// If `relative_priority` is out of range.  libdispatch would silently return `NULL` in this case, discarding the other attributes.
+    pub fn qos(self, qos: QoS, relative_priority: c_int) -> Self {
+        assert!((-15..=0).contains(&relative_priority), "relative_priority {} out of range -15..=0", relative_priority);
+        QueueAttributes {
+            qos: Some((qos, relative_priority)),
+            ..self
+        }
+    }
+    ///Applies `dispatch_queue_attr_make_with_autorelease_frequency`.
+    pub fn autorelease_frequency(self, frequency: AutoreleaseFrequency) -> Self {
+        QueueAttributes {
+            autorelease_frequency: Some(frequency),
+            ..self
+        }
+    }
+    ///Applies `dispatch_queue_attr_make_initially_inactive`.
+    ///
+    /// Only [Inactive::new] uses this, so that every inactive queue is wrapped in [Inactive].
+    fn initially_inactive(self) -> Self {
+        QueueAttributes {
+            initially_inactive: true,
+            ..self
+        }
+    }
+    ///Builds the `dispatch_queue_attr_t`.
+    ///
+    /// These are immortal objects managed by libdispatch, they don't need to be released.
+    fn as_raw(&self) -> *const c_void {
+        unsafe {
+            let mut attr: *const c_void = if self.concurrent { &_dispatch_queue_attr_concurrent } else { std::ptr::null() };
+            if self.initially_inactive {
+                attr = dispatch_queue_attr_make_initially_inactive(attr);
+            }
+            if let Some(frequency) = self.autorelease_frequency {
+                attr = dispatch_queue_attr_make_with_autorelease_frequency(attr, frequency.as_raw());
+            }
+            if let Some((qos, relative_priority)) = self.qos {
+                attr = dispatch_queue_attr_make_with_qos_class(attr, qos.as_raw(), relative_priority);
+            }
+            attr
+        }
+    }
+}
+impl Default for QueueAttributes {
+    fn default() -> Self {
+        Self::serial()
+    }
+}
+
+/**
+Memory-managed dispatch queue, such as one created by `dispatch_queue_create`.
+
+Cloning the queue retains it, and dropping it releases it.  Derefs to [Unmanaged], so all the submission
+functions are available.
+
+Compare with [global] and [main], which return queues that don't need memory management.
+*/
+#[derive(Debug)]
+pub struct Managed(*const Unmanaged);
+impl Managed {
+    ///Retains an unmanaged queue, such as one returned by [global] or [main].
+    pub fn retain(queue: &Unmanaged) -> Self {
+        queue.retain();
+        Self(queue)
+    }
+    ///Takes ownership of a +1 queue returned by some other creation function.
+    ///
+    /// # Safety
+    /// `queue` must be a valid queue, and the caller must own a reference to it, which is transferred to the result.
+    pub(crate) unsafe fn assume_retained(queue: *const Unmanaged) -> Self {
+        Self(queue)
+    }
+    ///Creates a new queue with the specified label and attributes, via `dispatch_queue_create`.
+    pub fn new(label: &CStr, attributes: &QueueAttributes) -> Self {
+        unsafe {
+            let queue = dispatch_queue_create(label.as_ptr(), attributes.as_raw());
+            tag_current(queue);
+            Self(queue)
+        }
+    }
+    ///Creates a new serial queue with the specified label, via `dispatch_queue_create`.
+    ///
+    /// Work submitted to a serial queue executes one at a time, in FIFO order.  A private serial queue
+    /// is a common way to confine some state.
+    pub fn new_serial(label: &CStr) -> Self {
+        Self::new(label, &QueueAttributes::serial())
+    }
+    ///Creates a new concurrent queue with the specified label, via `dispatch_queue_create`.
+    ///
+    /// Work submitted to a concurrent queue may execute simultaneously.
+    pub fn new_concurrent(label: &CStr) -> Self {
+        Self::new(label, &QueueAttributes::concurrent())
+    }
+    ///Calls `dispatch_activate`, which has no effect: a [Managed] queue is always active.
+    ///
+    /// This remains for compatibility.  To configure a queue before activating it, see [Inactive].
+    #[deprecated(note = "Managed queues are always active; use `Inactive` to configure a queue before activation")]
+    pub fn activate(&self) {
+        crate::object::activate(&**self)
+    }
+    ///Calls `dispatch_queue_set_specific`, associating `value` with `key` on this queue.
+    ///
+    /// Work running on this queue, or on any queue that targets it, can find the value with [get_specific].
+    /// Any previous value for the key is dropped, once no [get_specific] caller holds it.  The value is dropped when the queue is deallocated.
+    pub fn set_specific<T: Send + Sync + 'static>(&self, key: &'static SpecificKey<T>, value: T) {
+        let context = Arc::into_raw(Arc::new(value)) as *mut c_void;
+        extern "C" fn destructor<T>(context: *mut c_void) {
+            unsafe{ drop(Arc::from_raw(context as *const T)) }
+        }
+        key.lock.with(|| unsafe {
+            dispatch_queue_set_specific(self.0, key.as_ptr(), context, Some(destructor::<T>));
+        });
+    }
+}
+
+/**
+A queue that was created inactive, and can still be configured, such as with [Inactive::set_target_queue].  See [crate::object::Inactive].
+
+```no_run
+use dispatchr::queue::{Inactive, Managed, QueueAttributes};
+use dispatchr::QoS;
+let bottleneck = Managed::new(std::ffi::CStr::from_bytes_with_nul(b"com.example.bottleneck\0").unwrap(), &QueueAttributes::serial().qos(QoS::Utility, 0));
+let subsystem = Inactive::new(std::ffi::CStr::from_bytes_with_nul(b"com.example.subsystem\0").unwrap(), &QueueAttributes::serial());
+subsystem.set_target_queue(&bottleneck);
+let subsystem: Managed = subsystem.activate();
+```
+*/
+pub type Inactive = crate::object::Inactive<Managed>;
+impl Inactive {
+    ///Creates a new queue via `dispatch_queue_create`, with `attributes` plus `dispatch_queue_attr_make_initially_inactive`.
+    pub fn new(label: &CStr, attributes: &QueueAttributes) -> Self {
+        Inactive::from_inactive(Managed::new(label, &attributes.initially_inactive()))
+    }
+    ///Calls `dispatch_set_target_queue`.
+    ///
+    /// Work on this queue will execute on `target` (so with its QoS, and serialized with its other work, if it's a serial queue).
+    pub fn set_target_queue(&self, target: &Unmanaged) {
+        unsafe{ dispatch_set_target_queue(&***self as *const Unmanaged as *const c_void, target) }
+    }
+}
+
+/**
+A typed key for [Managed::set_specific] and [get_specific].
+
+Keys are compared by address, so declare them in a `static`.
+
+```no_run
+use dispatchr::queue::{Managed, SpecificKey, get_specific};
+static ON_PRIVATE_QUEUE: SpecificKey<()> = SpecificKey::new();
+let queue = Managed::new_serial(std::ffi::CStr::from_bytes_with_nul(b"com.example.private\0").unwrap());
+queue.set_specific(&ON_PRIVATE_QUEUE, ());
+//avoid deadlocking when we're already on the queue
+if get_specific(&ON_PRIVATE_QUEUE).is_some() {
+    //do the work directly
+}
+else {
+    queue.sync_ret(|| { /* do the work */ });
+}
+```
+*/
+#[derive(Debug)]
+pub struct SpecificKey<T> {
+    //serializes reading a value with replacing it
+    lock: Lock,
+    _marker: PhantomData<T>,
+}
+impl<T> SpecificKey<T> {
+    ///Creates a new key.  Use this to initialize a `static`.
+    pub const fn new() -> Self {
+        SpecificKey {
+            lock: Lock::new(),
+            _marker: PhantomData,
+        }
+    }
+    fn as_ptr(&self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+}
+impl<T> Default for SpecificKey<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///Calls `dispatch_get_specific`, finding the value for `key` on the current queue or the queues it targets.
+///
+/// Returns `None` when the current queue hierarchy has no value for `key`, such as when called outside of a dispatch queue.
+/// The value is shared rather than borrowed, since [Managed::set_specific] may replace (and drop) it while the caller is still using it.
+pub fn get_specific<T: Send + Sync + 'static>(key: &'static SpecificKey<T>) -> Option<Arc<T>> {
+    key.lock.with(|| {
+        let context = unsafe{ dispatch_get_specific(key.as_ptr()) } as *const T;
+        if context.is_null() {
+            None
+        }
+        else {
+            //the queue owns one count, we take another
+            unsafe {
+                Arc::increment_strong_count(context);
+                Some(Arc::from_raw(context))
+            }
+        }
+    })
+}
+impl Deref for Managed {
+    type Target = Unmanaged;
+
+    fn deref(&self) -> &Self::Target {
+        //valid for the lifetime of self
+        unsafe{&*self.0}
+    }
+}
+impl Clone for Managed {
+    fn clone(&self) -> Self {
+        self.retain();
+        Self(self.0)
+    }
+}
+impl Drop for Managed {
+    fn drop(&mut self) {
+        unsafe{ self.release() }
+    }
+}
+unsafe impl Send for Managed {}
+unsafe impl Sync for Managed {}
+
 extern "C" {
+    fn dispatch_queue_create(label: *const c_char, attr: *const c_void) -> *const Unmanaged;
+    ///`DISPATCH_QUEUE_CONCURRENT`.  Not a real type; only the address is used.
+    static _dispatch_queue_attr_concurrent: c_void;
+    fn dispatch_queue_attr_make_with_qos_class(attr: *const c_void, qos_class: c_uint, relative_priority: c_int) -> *const c_void;
+    fn dispatch_queue_attr_make_initially_inactive(attr: *const c_void) -> *const c_void;
+    fn dispatch_queue_attr_make_with_autorelease_frequency(attr: *const c_void, frequency: c_ulong) -> *const c_void;
+    pub(crate) fn dispatch_set_target_queue(object: *const c_void, queue: *const Unmanaged);
+    fn dispatch_queue_set_specific(queue: *const Unmanaged, key: *const c_void, context: *mut c_void, destructor: Option<extern "C" fn(*mut c_void)>);
+    fn dispatch_get_specific(key: *const c_void) -> *mut c_void;
+    ///pass null for DISPATCH_CURRENT_QUEUE_LABEL
+    fn dispatch_queue_get_label(queue: *const Unmanaged) -> *const c_char;
+    fn dispatch_queue_get_qos_class(queue: *const Unmanaged, relative_priority: *mut c_int) -> c_uint;
+    //Apple's headers alias these to a newer ABI
+    #[cfg_attr(target_vendor = "apple", link_name = "dispatch_assert_queue$V2")]
+    fn dispatch_assert_queue(queue: *const Unmanaged);
+    #[cfg_attr(target_vendor = "apple", link_name = "dispatch_assert_queue_not$V2")]
+    fn dispatch_assert_queue_not(queue: *const Unmanaged);
+    fn dispatch_assert_queue_barrier(queue: *const Unmanaged);
     fn dispatch_get_global_queue(identifier: c_uint, flags: *const c_void) -> *const Unmanaged;
     static _dispatch_main_q: Unmanaged;
     ///block parameter is actually &DispatchSyncBlock
     fn dispatch_sync(queue: &Unmanaged, block: *const c_void);
+    ///block parameter is actually &DispatchSyncBlock
+    fn dispatch_async_and_wait(queue: &Unmanaged, block: *const c_void);
+    ///block parameter is actually &DispatchSyncBlock
+    fn dispatch_barrier_sync(queue: &Unmanaged, block: *const c_void);
+    ///block parameter is actually &AsyncBlock
+    fn dispatch_async(queue: &Unmanaged, block: *mut c_void);
+    ///block parameter is actually &AsyncBlock
+    fn dispatch_after(when: Time, queue: &Unmanaged, block: *mut c_void);
+    ///block parameter is actually &AsyncBlock
+    fn dispatch_barrier_async(queue: &Unmanaged, block: *mut c_void);
+    fn dispatch_apply_f(iterations: usize, queue: &Unmanaged, context: *mut c_void, work: extern "C" fn(*mut c_void, usize));
     fn dispatch_async_f(queue: &Unmanaged, context: *const c_void, work: extern "C" fn (*const c_void));
 }
 
@@ -75,13 +616,127 @@ pub fn global(qos: QoS) -> Option<&'static Unmanaged> {
     }
 }
 
+///Like `dispatch_queue_get_label(DISPATCH_CURRENT_QUEUE_LABEL)`, returns the label of the queue the current work is running on.
+///
+/// The label is copied, since it's only valid as long as the current queue.
+pub fn current_label() -> CString {
+    let label = unsafe{ dispatch_queue_get_label(std::ptr::null()) };
+    if label.is_null() {
+        CString::default()
+    }
+    else {
+        unsafe{ CStr::from_ptr(label) }.to_owned()
+    }
+}
+
 ///Like Swift `DispatchQueue.main` or `dispatch_get_main_queue()`
 ///
 /// <https://developer.apple.com/documentation/dispatch/dispatchqueue/1781006-main>
 pub fn main() -> &'static Unmanaged {
-    unsafe { &_dispatch_main_q }
+    static TAG: std::sync::Once = std::sync::Once::new();
+    let queue = unsafe { &_dispatch_main_q };
+    TAG.call_once(|| unsafe{ tag_current(queue) });
+    queue
 }
 
+///Tags `queue` for [Unmanaged::is_current]: the queue's address is a unique key, and its own value.
+///
+/// # Safety
+/// `queue` must be a queue that supports queue-specific data (so not a workloop), and nothing else may use its address as a key.
+unsafe fn tag_current(queue: *const Unmanaged) {
+    let key = queue as *const c_void;
+    dispatch_queue_set_specific(queue, key, key as *mut c_void, None);
+}
+
+///Calls `dispatch_main`, which parks the main thread and executes work submitted to [main].  Never returns.
+///
+/// Call this at the end of `fn main` in programs that use the main queue but don't run a `CFRunLoop` or `NSApplication`.
+/// To finish, call [std::process::exit] from some work item.  For a variant that returns, see [run_main_until].
+pub fn run_main() -> ! {
+    unsafe{ dispatch_main() }
+}
+
+///Drains the main queue on the current thread until `condition` returns `true`.
+///
+/// This must be called from the main thread.  It's useful for integration tests and command-line tools that need to
+/// drive [main] for a while, then return.  Between drains, the thread sleeps until libdispatch signals new work for the main queue.
+///
+/// `condition` is only checked after each drain, so it should become `true` as a result of work on the main queue.  To stop from
+/// another thread, submit work to [main] that makes it `true`.
+///
+/// Don't combine this with [run_main] or a running `CFRunLoop`, which drain the main queue themselves.
+///
+/// # Panics
+/// If called from a thread other than the main thread.
+pub fn run_main_until<F: FnMut() -> bool>(mut condition: F) {
+    assert!(is_main_thread(), "run_main_until must be called from the main thread");
+    let handle = unsafe{ main_queue_handle() };
+    while !condition() {
+        unsafe{ _dispatch_main_queue_callback_4CF(std::ptr::null_mut()) };
+        if condition() {
+            break;
+        }
+        //libdispatch signals new work on the handle, as it would wake a run loop
+        unsafe{ wait_for_main_queue(handle) };
+    }
+}
+
+//The main queue is drained by CoreFoundation with these SPI.  Asking for the wakeup handle lets libdispatch know
+//that the main queue is being drained by a run loop.
+extern "C" {
+    fn dispatch_main() -> !;
+    fn _dispatch_main_queue_callback_4CF(msg: *mut c_void);
+    #[cfg(target_vendor = "apple")]
+    fn _dispatch_get_main_queue_port_4CF() -> c_uint;
+    #[cfg(not(target_vendor = "apple"))]
+    fn _dispatch_get_main_queue_handle_4CF() -> c_int;
+}
+#[cfg(target_vendor = "apple")]
+fn is_main_thread() -> bool {
+    unsafe{ libc::pthread_main_np() != 0 }
+}
+#[cfg(target_os = "linux")]
+fn is_main_thread() -> bool {
+    unsafe{ libc::gettid() == libc::getpid() }
+}
+#[cfg(not(any(target_vendor = "apple", target_os = "linux")))]
+fn is_main_thread() -> bool {
+    //no portable way to tell
+    true
+}
+#[cfg(target_vendor = "apple")]
+unsafe fn main_queue_handle() -> c_uint {
+    _dispatch_get_main_queue_port_4CF()
+}
+#[cfg(not(target_vendor = "apple"))]
+unsafe fn main_queue_handle() -> c_int {
+    _dispatch_get_main_queue_handle_4CF()
+}
+
+#[cfg(target_vendor = "apple")]
+extern "C" {
+    ///msg parameter is actually *mut mach_msg_header_t
+    fn mach_msg(msg: *mut c_void, option: c_int, send_size: c_uint, rcv_size: c_uint, rcv_name: c_uint, timeout: c_uint, notify: c_uint) -> c_int;
+}
+///Blocks until libdispatch sends a wakeup message to the main queue's port.  The message has no payload we need.
+#[cfg(target_vendor = "apple")]
+unsafe fn wait_for_main_queue(port: c_uint) {
+    const MACH_RCV_MSG: c_int = 0x2;
+    //room for the wakeup message and its trailer
+    #[repr(C, align(8))]
+    struct Buffer([u8; 1024]);
+    let mut buffer = Buffer([0; 1024]);
+    mach_msg(buffer.0.as_mut_ptr() as *mut c_void, MACH_RCV_MSG, 0, buffer.0.len() as c_uint, port, 0, 0);
+}
+///Blocks until libdispatch signals the main queue's eventfd, then resets it.
+#[cfg(not(target_vendor = "apple"))]
+unsafe fn wait_for_main_queue(fd: c_int) {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    //on EINTR, the caller drains and comes back
+    libc::poll(&mut pollfd, 1, -1);
+    let mut value: u64 = 0;
+    libc::read(fd, &mut value as *mut u64 as *mut c_void, std::mem::size_of::<u64>());
+}
 
 #[test] fn get_queue() {
     let _queue = unsafe{ dispatch_get_global_queue(QoS::UserInitiated.as_raw(), std::ptr::null()) };
@@ -90,3 +745,229 @@ pub fn main() -> &'static Unmanaged {
     println!("{:?}",_queue2);
 }
 
+#[test] fn create_queue() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.create_queue\0").unwrap();
+    let serial = Managed::new_serial(label);
+    let clone = serial.clone();
+    drop(serial);
+    assert_eq!(clone.sync_ret(|| 2 + 2), 4);
+
+    let concurrent = Managed::new_concurrent(label);
+    assert_eq!(concurrent.sync_ret(|| 3), 3);
+}
+
+#[test] fn async_closure() {
+    let (sender,receiver) = std::sync::mpsc::channel();
+    global(QoS::Default).unwrap().async_closure(move || {
+        sender.send(23).unwrap();
+    });
+    assert_eq!(receiver.recv_timeout(std::time::Duration::from_secs(1)), Ok(23));
+}
+
+#[test] fn async_closure_panic() {
+    struct DropCounter(std::sync::mpsc::Sender<()>);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.send(()).unwrap();
+        }
+    }
+    let (sender,receiver) = std::sync::mpsc::channel();
+    let counter = DropCounter(sender);
+    global(QoS::Default).unwrap().async_closure(move || {
+        let _counter = counter;
+        panic!("panic from async_closure");
+    });
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+    //dropped once; the sender is now gone
+    assert!(receiver.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+}
+
+#[test] fn queue_attributes() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.queue_attributes\0").unwrap();
+    let attributes = QueueAttributes::concurrent()
+        .qos(QoS::Utility, -2)
+        .autorelease_frequency(AutoreleaseFrequency::WorkItem);
+    let queue = Inactive::new(label, &attributes);
+    let (sender,receiver) = std::sync::mpsc::channel();
+    queue.async_closure(move || {
+        sender.send(()).unwrap();
+    });
+    //inactive queues don't run work
+    assert!(receiver.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+    let _queue = queue.activate();
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+}
+
+#[test] fn barrier() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let label = CStr::from_bytes_with_nul(b"dispatchr.barrier\0").unwrap();
+    let queue = Managed::new_concurrent(label);
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let counter = counter.clone();
+        queue.async_closure(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    let move_counter = counter.clone();
+    queue.barrier_async(move || {
+        //all readers before the barrier have finished
+        assert_eq!(move_counter.load(Ordering::Relaxed), 10);
+    });
+    let observed = queue.barrier_sync_ret(|| counter.load(Ordering::Relaxed));
+    assert_eq!(observed, 10);
+}
+
+#[test] fn after() {
+    use std::time::{Duration, Instant};
+    let queue = global(QoS::Default).unwrap();
+    let (sender,receiver) = std::sync::mpsc::channel();
+    let started = Instant::now();
+    queue.after(Time::after(Duration::from_millis(50)), move || {
+        sender.send(started.elapsed()).unwrap();
+    });
+    let elapsed = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(elapsed >= Duration::from_millis(50));
+
+    let (sender,receiver) = std::sync::mpsc::channel();
+    queue.after(Time::NOW.new_after(1_000_000), move || {
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+}
+
+#[test] fn apply() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let queue = global(QoS::UserInitiated).unwrap();
+    //borrow from the stack
+    let sum = AtomicUsize::new(0);
+    queue.apply(100, |i| {
+        sum.fetch_add(i, Ordering::Relaxed);
+    });
+    assert_eq!(sum.into_inner(), 4950);
+
+    let mut buffer = vec![0_u8; 1000];
+    queue.apply_chunks_mut(&mut buffer, 64, |index, chunk| {
+        for byte in chunk {
+            *byte = index as u8;
+        }
+    });
+    assert_eq!(buffer[0], 0);
+    assert_eq!(buffer[64], 1);
+    assert_eq!(buffer[999], 15);
+
+    //one chunk, when chunk_size is longer than the slice
+    for chunk_size in [2000, usize::MAX] {
+        let mut buffer = vec![0_u8; 1000];
+        let calls = AtomicUsize::new(0);
+        queue.apply_chunks_mut(&mut buffer, chunk_size, |index, chunk| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            assert_eq!(index, 0);
+            assert_eq!(chunk.len(), 1000);
+            chunk.fill(1);
+        });
+        assert_eq!(calls.into_inner(), 1);
+        assert!(buffer.iter().all(|byte| *byte == 1));
+    }
+}
+
+#[test] fn apply_panic() {
+    let queue = global(QoS::UserInitiated).unwrap();
+    let result = std::panic::catch_unwind(|| {
+        queue.apply(10, |i| {
+            if i == 5 {
+                panic!("panic from apply");
+            }
+        });
+    });
+    assert!(result.is_err());
+}
+
+#[test] fn specific() {
+    static KEY: SpecificKey<&'static str> = SpecificKey::new();
+    let label = CStr::from_bytes_with_nul(b"dispatchr.specific\0").unwrap();
+    let queue = Managed::new_serial(label);
+    queue.set_specific(&KEY, "private");
+    assert!(get_specific(&KEY).is_none());
+    let value = queue.sync_ret(|| get_specific(&KEY));
+    assert_eq!(value.as_deref(), Some(&"private"));
+    //replacing is fine while we hold the old value
+    queue.set_specific(&KEY, "replaced");
+    assert_eq!(value.as_deref(), Some(&"private"));
+    assert_eq!(queue.sync_ret(|| get_specific(&KEY)).as_deref(), Some(&"replaced"));
+}
+
+#[test] fn assertions() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.assertions\0").unwrap();
+    let queue = Managed::new_serial(label);
+    queue.assert_not_current();
+    assert!(!queue.is_current());
+    let inner = queue.clone();
+    let on_queue = queue.sync_ret(move || {
+        inner.assert_current();
+        inner.assert_barrier();
+        inner.is_current()
+    });
+    assert!(on_queue);
+    assert!(!global(QoS::Default).unwrap().is_current());
+}
+
+#[test] fn target_queue() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.target_queue\0").unwrap();
+    let bottleneck = Managed::new_serial(label);
+    let subsystem = Inactive::new(label, &QueueAttributes::concurrent());
+    subsystem.set_target_queue(&bottleneck);
+    let subsystem = subsystem.activate();
+    let inner = bottleneck.clone();
+    //work on the subsystem executes on the bottleneck
+    assert!(subsystem.sync_ret(move || inner.is_current()));
+
+    //dropping without activating is fine
+    let _ = Inactive::new(label, &QueueAttributes::serial());
+}
+
+#[test] fn introspection() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.introspection\0").unwrap();
+    let queue = Managed::new(label, &QueueAttributes::serial().qos(QoS::Utility, -3));
+    assert_eq!(queue.label(), label);
+    assert_eq!(queue.qos(), (QoS::Utility, -3));
+    assert_eq!(queue.sync_ret(current_label).as_c_str(), label);
+    assert_eq!(main().qos().0, QoS::Unspecified);
+}
+
+#[test] fn async_and_wait() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.async_and_wait\0").unwrap();
+    let queue = Managed::new_serial(label);
+    let inner = queue.clone();
+    assert!(queue.async_and_wait_ret(move || inner.is_current()));
+
+    use std::mem::MaybeUninit;
+    let mut block_value = MaybeUninit::uninit();
+    let block_value = unsafe{ Pin::new_unchecked(&mut block_value) };
+    let mut run = false;
+    let block_value = unsafe{ DispatchSyncBlock::new(block_value, || {
+        run = true;
+    })};
+    queue.async_and_wait(&block_value);
+    assert!(run);
+}
+
+#[test] fn run() {
+    use crate::executor::block_on;
+    let label = CStr::from_bytes_with_nul(b"dispatchr.run\0").unwrap();
+    let queue = Managed::new_serial(label);
+    let inner = queue.clone();
+    assert!(block_on(queue.run(move || inner.is_current())));
+
+    //dropping before the first poll never submits the closure
+    let (sender,receiver) = std::sync::mpsc::channel::<()>();
+    drop(queue.run(move || sender.send(()).unwrap()));
+    assert_eq!(receiver.recv_timeout(std::time::Duration::from_millis(100)), Err(std::sync::mpsc::RecvTimeoutError::Disconnected));
+}
+
+#[test] fn run_main_until_off_main_thread() {
+    //the test harness runs tests on other threads
+    let result = std::thread::spawn(|| run_main_until(|| true)).join();
+    assert!(result.is_err());
+}
@@ -2,7 +2,7 @@
 
 use std::ffi::c_void;
 use std::ops::Deref;
-use crate::data::{dispatch_release, dispatch_retain};
+use crate::object::Object;
 use crate::time::Time;
 
 extern "C" {
@@ -14,22 +14,13 @@ extern "C" {
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct Unmanaged(c_void);
+unsafe impl Object for Unmanaged {}
 
 impl Unmanaged {
     ///Creates an unmanaged DispatchSemaphore.
     pub fn new(value: isize) -> *mut Unmanaged {
         unsafe { dispatch_semaphore_create(value)}
     }
-    ///# Safety
-    ///
-    /// Undefined behavior to use the argument after calling this function.
-    unsafe fn release(&mut self) {
-        //'safe' because
-        //1.  We are guaranteed to have an exclusive reference by Rust semantics
-        //2.  Reference assumed to be valid by Rust semantics
-        //3.  Caller guarantees we only do this once.
-        dispatch_release(self as *mut Unmanaged as *const Unmanaged as *const c_void)
-    }
     ///Calls dispatch_semaphore_wait.
     pub fn wait(&self, time: Time) -> isize {
         unsafe {
@@ -54,7 +45,7 @@ impl Managed {
 }
 impl Drop for Managed {
     fn drop(&mut self) {
-        unsafe{ (&mut *(self.0)).release()};
+        unsafe{ (*self.0).release() };
     }
 }
 impl Deref for Managed {
@@ -66,7 +57,7 @@ impl Deref for Managed {
 }
 impl Clone for Managed {
     fn clone(&self) -> Self {
-        unsafe{dispatch_retain(self.0 as *const c_void)};
+        self.retain();
         Managed {
             0: self.0
         }
@@ -79,7 +70,7 @@ unsafe impl Send for Managed {}
     let f = Unmanaged::new(0);
     unsafe {
         //give f an unbounded lifetime
-        let f: &mut Unmanaged = &mut *f;
+        let f: &Unmanaged = &*f;
         f.release()
     }
 }
@@ -1,16 +1,28 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::ops::Deref;
-use std::os::raw::c_void;
+use std::ops::{BitOr, Deref};
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use libc::uintptr_t;
-use crate::data::dispatch_release;
+use crate::object::Object;
+use crate::block_impl::{handler_block, once_handler_block};
 use crate::time::Time;
 
+/**
+A dispatch source of any kind.
+
+Sources are created through one of the typed kinds, like [TimerSource] or [DataAddSource], which deref to this type for the
+operations all kinds share.  Kind-specific accessors (like the pending data) live on the kinds themselves.
+*/
 #[repr(transparent)]
 pub struct Unmanaged(c_void);
+unsafe impl Object for Unmanaged {}
 impl Unmanaged {
     /**
     Calls dispatch_source_create.*/
+    #[deprecated(note = "use a typed source, like `TimerSource::new`")]
     pub fn create(tipe: dispatch_source_type_t, handle: uintptr_t, mask: uintptr_t, queue: &crate::queue::Unmanaged) -> *mut Unmanaged {
         unsafe{dispatch_source_create(tipe, handle, mask, queue)}
     }
@@ -19,14 +31,55 @@ impl Unmanaged {
             dispatch_source_set_event_handler_f(self, handler)
         }
     }
+    ///Calls `dispatch_source_set_event_handler`.
+    ///
+    /// The handler is called on the target queue each time the source fires.  Calls are serialized, so the handler may be `FnMut`.
+    /// It's owned by the source, and dropped when it's replaced, or after the source is cancelled and released.
+    pub fn set_event_handler<F: FnMut() + Send + 'static>(&self, handler: F) {
+        unsafe {
+            let mut block = handler_block(handler);
+            dispatch_source_set_event_handler(self, &mut block as *mut _ as *mut c_void)
+        }
+    }
+    ///Calls `dispatch_source_set_cancel_handler`.
+    ///
+    /// The handler is called once on the target queue after [Unmanaged::cancel], once no event handler is running.  This is the place to
+    /// release resources (like file descriptors) the source is monitoring.  If the source is never cancelled, the handler is dropped without running.
+    pub fn set_cancel_handler<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        unsafe {
+            let mut block = once_handler_block(handler);
+            dispatch_source_set_cancel_handler(self, &mut block as *mut _ as *mut c_void)
+        }
+    }
+    ///Calls `dispatch_source_set_registration_handler`.
+    ///
+    /// The handler is called once on the target queue, after the source is activated and registered with the system.
+    pub fn set_registration_handler<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        unsafe {
+            let mut block = once_handler_block(handler);
+            dispatch_source_set_registration_handler(self, &mut block as *mut _ as *mut c_void)
+        }
+    }
+    #[deprecated(note = "use `TimerSource::set_timer`")]
     pub fn set_timer(self: &Unmanaged, time: Time, interval: u64, leeway: u64) {
         unsafe {
             dispatch_source_set_timer(self, time, interval, leeway)
         }
     }
+    ///Calls `dispatch_suspend`.  Each call must be balanced by a call to [Unmanaged::resume].
+    ///
+    /// Since this inherent method shadows [Object::suspend], call that as `Object::suspend(&*source)` to get a guard.
+    #[deprecated(note = "use `Object::suspend`, whose guard resumes")]
+    pub fn suspend(&self) {
+        unsafe {
+            crate::object::dispatch_suspend(self as *const _ as *const c_void)
+        }
+    }
+    ///Calls `dispatch_resume`.  For a new source, this activates it.
+    #[deprecated(note = "use `Inactive::activate`, or `Object::suspend`, whose guard resumes")]
     pub fn resume(&self) {
         unsafe {
-            dispatch_resume(self as *const _ as *const c_void)
+            crate::object::dispatch_resume(self as *const _ as *const c_void)
         }
     }
     pub fn cancel(&self) {
@@ -34,9 +87,28 @@ impl Unmanaged {
             dispatch_source_cancel(self)
         }
     }
-    pub fn suspend(&self) {
+    ///Calls `dispatch_source_testcancel`.
+    pub fn is_cancelled(&self) -> bool {
+        unsafe {
+            dispatch_source_testcancel(self) != 0
+        }
+    }
+    ///Calls `dispatch_source_get_data`.  The meaning depends on the kind, so the kinds expose it under a better name.
+    fn data(&self) -> uintptr_t {
+        unsafe {
+            dispatch_source_get_data(self)
+        }
+    }
+    ///Calls `dispatch_source_get_handle`.  Only meaningful for kinds created with a handle.
+    fn handle(&self) -> uintptr_t {
+        unsafe {
+            dispatch_source_get_handle(self)
+        }
+    }
+    ///Calls `dispatch_source_merge_data`.  Only meaningful for the data kinds.
+    fn merge_data(&self, value: uintptr_t) {
         unsafe {
-            dispatch_suspend(self as *const _ as *const c_void);
+            dispatch_source_merge_data(self, value)
         }
     }
 }
@@ -49,15 +121,30 @@ pub struct Managed(*mut Unmanaged);
 impl Drop for Managed {
     fn drop(&mut self) {
         unsafe {
-            dispatch_release(self.0 as *mut c_void)
+            (*self.0).release()
         }
     }
 }
 impl Managed {
     /**
-    Calls dispatch_source_create.*/
+    Calls dispatch_source_create.
+
+    libdispatch returns NULL if `handle` or `mask` isn't valid for `tipe`; that panics here, though the typed kinds
+    are built so that it can't happen.*/
+    fn new(tipe: dispatch_source_type_t, handle: uintptr_t, mask: uintptr_t, queue: &crate::queue::Unmanaged) -> Self {
+        let ptr = unsafe{dispatch_source_create(tipe, handle, mask, queue)};
+        assert!(!ptr.is_null(), "dispatch_source_create failed");
+        Self(ptr)
+    }
+    /**
+    Calls dispatch_source_create.
+
+    The source is created inactive, and libdispatch crashes if it's released that way, so activate it first.
+    # Panics
+    If `handle` or `mask` isn't valid for `tipe`.*/
+    #[deprecated(note = "use a typed source, like `TimerSource::new`")]
     pub fn create(tipe: dispatch_source_type_t, handle: uintptr_t, mask: uintptr_t, queue: &crate::queue::Unmanaged) -> Self {
-        Self(Unmanaged::create(tipe, handle, mask, queue))
+        Self::new(tipe, handle, mask, queue)
     }
 }
 impl Deref for Managed {
@@ -71,18 +158,444 @@ impl Deref for Managed {
 unsafe impl Send for Managed {}
 unsafe impl Sync for Managed {}
 
+/**
+A source that hasn't been activated yet.  See [crate::object::Inactive].
+
+Sources are created inactive, so that handlers, the target queue, and kind-specific settings (like [TimerSource::set_timer])
+can be configured before any events are delivered.
+
+```no_run
+use dispatchr::source::TimerSource;
+use dispatchr::QoS;
+let timer = TimerSource::new(dispatchr::queue::global(QoS::Default).unwrap());
+timer.set_timer(dispatchr::time::Time::after(std::time::Duration::from_secs(1)), Some(std::time::Duration::from_secs(1)), std::time::Duration::ZERO);
+timer.set_event_handler(|| println!("tick"));
+let timer: TimerSource = timer.activate();
+```
+*/
+pub type Inactive<S> = crate::object::Inactive<S>;
+impl<S: Deref<Target=Unmanaged>> Inactive<S> {
+    ///Calls `dispatch_set_target_queue`, changing the queue the handlers are submitted to.
+    ///
+    /// This is only available before activation, since libdispatch crashes if the target of an active source is changed.
+    pub fn set_target_queue(&self, queue: &crate::queue::Unmanaged) {
+        unsafe {
+            crate::queue::dispatch_set_target_queue(&***self as *const Unmanaged as *const c_void, queue)
+        }
+    }
+}
+
+macro_rules! source_kind {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        pub struct $name(Managed);
+        impl Deref for $name {
+            type Target = Unmanaged;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    }
+}
+
+source_kind!(
+    /**
+    A timer source (`DISPATCH_SOURCE_TYPE_TIMER`).
+
+    Nothing fires until [TimerSource::set_timer] is called.
+    */
+    TimerSource
+);
+impl TimerSource {
+    ///Creates a timer source whose handlers are submitted to `queue`.
+    pub fn new(queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(TimerSource(Managed::new(dispatch_source_type_t::timer(), 0, 0, queue)))
+    }
+    ///Calls `dispatch_source_set_timer`.
+    ///
+    /// The timer first fires at `start` (for a delay from now, use [Time::after]), and then every `interval`;
+    /// pass `None` for a timer that fires once.  `leeway` is how late the system may deliver each event, to coalesce wakeups.
+    pub fn set_timer(&self, start: Time, interval: Option<Duration>, leeway: Duration) {
+        let interval = interval.map(duration_nanos).unwrap_or(Time::FOREVER.0);
+        unsafe {
+            dispatch_source_set_timer(&**self, start, interval, duration_nanos(leeway))
+        }
+    }
+    ///The number of times the timer fired since the event handler was last called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn fire_count(&self) -> usize {
+        self.data()
+    }
+}
+
+fn duration_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+source_kind!(
+    /**
+    A source that coalesces values from [DataAddSource::merge] by adding them (`DISPATCH_SOURCE_TYPE_DATA_ADD`).
+
+    Useful for counting events from many threads, with one handler call per batch.
+    */
+    DataAddSource
+);
+impl DataAddSource {
+    ///Creates a data-add source whose handlers are submitted to `queue`.
+    pub fn new(queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(DataAddSource(Managed::new(dispatch_source_type_t::data_add(), 0, 0, queue)))
+    }
+    ///Adds `value` to the pending data and schedules the event handler.  Merging 0 does nothing.
+    pub fn merge(&self, value: usize) {
+        self.merge_data(value)
+    }
+    ///The sum of the values merged since the event handler was last called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn data(&self) -> usize {
+        (**self).data()
+    }
+}
+
+source_kind!(
+    /**
+    A source that coalesces values from [DataOrSource::merge] with bitwise or (`DISPATCH_SOURCE_TYPE_DATA_OR`).
+
+    Useful for collecting a set of flags from many threads, with one handler call per batch.
+    */
+    DataOrSource
+);
+impl DataOrSource {
+    ///Creates a data-or source whose handlers are submitted to `queue`.
+    pub fn new(queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(DataOrSource(Managed::new(dispatch_source_type_t::data_or(), 0, 0, queue)))
+    }
+    ///Ors `value` into the pending data and schedules the event handler.  Merging 0 does nothing.
+    pub fn merge(&self, value: usize) {
+        self.merge_data(value)
+    }
+    ///The bits merged since the event handler was last called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn data(&self) -> usize {
+        (**self).data()
+    }
+}
+
+source_kind!(
+    /**
+    A source that keeps only the latest value from [DataReplaceSource::merge] (`DISPATCH_SOURCE_TYPE_DATA_REPLACE`).
+    */
+    DataReplaceSource
+);
+impl DataReplaceSource {
+    ///Creates a data-replace source whose handlers are submitted to `queue`.
+    pub fn new(queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(DataReplaceSource(Managed::new(dispatch_source_type_t::data_replace(), 0, 0, queue)))
+    }
+    ///Replaces the pending data with `value` and schedules the event handler.  Merging 0 does nothing.
+    pub fn merge(&self, value: usize) {
+        self.merge_data(value)
+    }
+    ///The value most recently merged before the event handler was called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn data(&self) -> usize {
+        (**self).data()
+    }
+}
+
+/**
+Shared implementation of [ReadSource] and [WriteSource].
+
+The source owns the file descriptor's owner, and drops it (closing the fd) from the cancel handler, since the fd must stay
+open until libdispatch is done monitoring it.
+*/
+struct FdSource {
+    source: Managed,
+    owner: Arc<Mutex<Option<Box<dyn Send>>>>,
+}
+impl FdSource {
+    fn new<F: AsRawFd + Send + 'static>(tipe: dispatch_source_type_t, fd: F, queue: &crate::queue::Unmanaged) -> Self {
+        let raw = fd.as_raw_fd();
+        assert!(raw >= 0, "invalid file descriptor");
+        let source = FdSource {
+            source: Managed::new(tipe, raw as uintptr_t, 0, queue),
+            owner: Arc::new(Mutex::new(Some(Box::new(fd)))),
+        };
+        source.set_cancel_handler(|| ());
+        source
+    }
+    fn set_cancel_handler<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        let owner = self.owner.clone();
+        self.source.set_cancel_handler(move || {
+            handler();
+            drop(owner.lock().unwrap_or_else(|e| e.into_inner()).take());
+        })
+    }
+    fn fd(&self) -> RawFd {
+        self.source.handle() as RawFd
+    }
+}
+impl Drop for FdSource {
+    fn drop(&mut self) {
+        //the fd is closed from the cancel handler, once libdispatch stops monitoring it
+        self.source.cancel();
+    }
+}
+
+/**
+A source that fires when a file descriptor has data to read (`DISPATCH_SOURCE_TYPE_READ`).
+
+The source takes ownership of `fd` (e.g. a [std::fs::File] or [std::os::unix::net::UnixStream]); put it in nonblocking mode
+if the handler reads more than [ReadSource::bytes_available].  Dropping the source cancels it, and the fd is dropped (closed)
+after cancellation completes, so it's never closed while libdispatch is still monitoring it.
+*/
+pub struct ReadSource(FdSource);
+impl ReadSource {
+    ///Creates a read source for `fd`, whose handlers are submitted to `queue`.
+    pub fn new<F: AsRawFd + Send + 'static>(fd: F, queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(ReadSource(FdSource::new(dispatch_source_type_t::read(), fd, queue)))
+    }
+    ///The file descriptor being monitored.
+    pub fn fd(&self) -> RawFd {
+        self.0.fd()
+    }
+    ///The estimated number of bytes available to read.
+    ///
+    /// Only meaningful from inside the event handler.  For sockets in listen mode, this is the number of pending connections instead.
+    pub fn bytes_available(&self) -> usize {
+        self.data()
+    }
+    ///Like [Unmanaged::set_cancel_handler], but the fd is closed after `handler` runs.
+    pub fn set_cancel_handler<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        self.0.set_cancel_handler(handler)
+    }
+}
+impl Deref for ReadSource {
+    type Target = Unmanaged;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.source
+    }
+}
+
+/**
+A source that fires when a file descriptor has buffer space to write into (`DISPATCH_SOURCE_TYPE_WRITE`).
+
+Ownership and cancellation work like [ReadSource].
+*/
+pub struct WriteSource(FdSource);
+impl WriteSource {
+    ///Creates a write source for `fd`, whose handlers are submitted to `queue`.
+    pub fn new<F: AsRawFd + Send + 'static>(fd: F, queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Inactive::from_inactive(WriteSource(FdSource::new(dispatch_source_type_t::write(), fd, queue)))
+    }
+    ///The file descriptor being monitored.
+    pub fn fd(&self) -> RawFd {
+        self.0.fd()
+    }
+    ///The estimated buffer space available to write into.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn buffer_space(&self) -> usize {
+        self.data()
+    }
+    ///Like [Unmanaged::set_cancel_handler], but the fd is closed after `handler` runs.
+    pub fn set_cancel_handler<F: FnOnce() + Send + 'static>(&self, handler: F) {
+        self.0.set_cancel_handler(handler)
+    }
+}
+impl Deref for WriteSource {
+    type Target = Unmanaged;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.source
+    }
+}
+
+source_kind!(
+    /**
+    A source that fires when the process receives a Unix signal (`DISPATCH_SOURCE_TYPE_SIGNAL`).
+
+    This is a safe alternative to a signal handler, as the event handler runs on a queue like any other work.
+    The source observes signals *in addition* to the process's signal disposition, so the default action (which for
+    `SIGTERM`, `SIGINT` and `SIGHUP` is to terminate) still happens unless it's changed; usually call [SignalSource::ignore] first.
+
+    ```no_run
+    use dispatchr::source::SignalSource;
+    use dispatchr::QoS;
+    SignalSource::ignore(libc::SIGTERM).unwrap();
+    let source = SignalSource::new(libc::SIGTERM, dispatchr::queue::global(QoS::Default).unwrap());
+    source.set_event_handler(|| println!("shutting down"));
+    let source = source.activate();
+    ```
+    */
+    SignalSource
+);
+impl SignalSource {
+    ///Creates a source for `signal` (like `libc::SIGTERM`), whose handlers are submitted to `queue`.
+    ///
+    /// # Panics
+    /// If `signal` isn't a valid signal number.
+    pub fn new(signal: c_int, queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        assert!(signal > 0, "invalid signal number {}", signal);
+        Inactive::from_inactive(SignalSource(Managed::new(dispatch_source_type_t::signal(), signal as uintptr_t, 0, queue)))
+    }
+    ///The signal being monitored.
+    pub fn signal(&self) -> c_int {
+        self.handle() as c_int
+    }
+    ///The number of times the signal was delivered since the event handler was last called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn count(&self) -> usize {
+        self.data()
+    }
+    ///Sets the process's disposition for `signal` to `SIG_IGN`, so its default action doesn't happen, while sources still observe it.
+    ///
+    /// This affects the whole process, including any handler previously installed for `signal`.
+    pub fn ignore(signal: c_int) -> std::io::Result<()> {
+        if unsafe{ libc::signal(signal, libc::SIG_IGN) } == libc::SIG_ERR {
+            Err(std::io::Error::last_os_error())
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+///Like `dispatch_source_proc_flags_t`: the process events a [ProcSource] monitors.  Combine events with `|`.
+#[repr(transparent)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub struct ProcEvents(c_ulong);
+impl ProcEvents {
+    ///`DISPATCH_PROC_EXIT`: the process exited.
+    pub const EXIT: ProcEvents = ProcEvents(0x80000000);
+    ///`DISPATCH_PROC_FORK`: the process created a child process.
+    pub const FORK: ProcEvents = ProcEvents(0x40000000);
+    ///`DISPATCH_PROC_EXEC`: the process became another executable image via `exec` or `posix_spawn`.
+    pub const EXEC: ProcEvents = ProcEvents(0x20000000);
+    ///`DISPATCH_PROC_SIGNAL`: a Unix signal was delivered to the process.
+    pub const SIGNAL: ProcEvents = ProcEvents(0x08000000);
+
+    const ALL: ProcEvents = ProcEvents(Self::EXIT.0 | Self::FORK.0 | Self::EXEC.0 | Self::SIGNAL.0);
+
+    ///The raw `dispatch_source_proc_flags_t` bits.
+    pub const fn bits(self) -> c_ulong {
+        self.0
+    }
+    ///Creates a set from raw `dispatch_source_proc_flags_t` bits, discarding any bits that aren't one of the events above.
+    pub const fn from_bits_truncate(bits: c_ulong) -> ProcEvents {
+        ProcEvents(bits & Self::ALL.0)
+    }
+    ///Whether all the events in `other` are also in `self`.
+    pub fn contains(self, other: ProcEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+impl BitOr for ProcEvents {
+    type Output = ProcEvents;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        ProcEvents(self.0 | rhs.0)
+    }
+}
+
+source_kind!(
+    /**
+    A source that fires on events in another process (`DISPATCH_SOURCE_TYPE_PROC`).
+
+    ```no_run
+    use dispatchr::source::{ProcSource, ProcEvents};
+    use dispatchr::QoS;
+    let child = std::process::Command::new("make").spawn().unwrap();
+    let source = ProcSource::watch_child(&child, ProcEvents::EXIT | ProcEvents::EXEC, dispatchr::queue::global(QoS::Default).unwrap());
+    source.set_event_handler(|events| {
+        if events.contains(ProcEvents::EXIT) {
+            println!("make exited");
+        }
+    });
+    let source = source.activate();
+    ```
+    */
+    ProcSource
+);
+impl ProcSource {
+    ///Creates a source for `events` in the process `pid`, whose handlers are submitted to `queue`.
+    ///
+    /// # Panics
+    /// If `pid` isn't positive.
+    pub fn new(pid: libc::pid_t, events: ProcEvents, queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        assert!(pid > 0, "invalid pid {}", pid);
+        Inactive::from_inactive(ProcSource(Managed::new(dispatch_source_type_t::proc(), pid as uintptr_t, events.0 as uintptr_t, queue)))
+    }
+    ///Creates a source for `events` in `child`.
+    ///
+    /// The source doesn't reap the child; after [ProcEvents::EXIT], call [std::process::Child::wait] (or `try_wait`) to collect its status.
+    pub fn watch_child(child: &std::process::Child, events: ProcEvents, queue: &crate::queue::Unmanaged) -> Inactive<Self> {
+        Self::new(child.id() as libc::pid_t, events, queue)
+    }
+    ///The process being monitored.
+    pub fn pid(&self) -> libc::pid_t {
+        self.handle() as libc::pid_t
+    }
+    ///The events being monitored.
+    pub fn events(&self) -> ProcEvents {
+        ProcEvents::from_bits_truncate(unsafe{ dispatch_source_get_mask(&**self) } as c_ulong)
+    }
+    ///The events that fired since the event handler was last called.
+    ///
+    /// Only meaningful from inside the event handler.
+    pub fn fired(&self) -> ProcEvents {
+        ProcEvents::from_bits_truncate(self.data() as c_ulong)
+    }
+    ///Like [Unmanaged::set_event_handler], but `handler` receives the events that fired (as [ProcSource::fired]).
+    pub fn set_event_handler<F: FnMut(ProcEvents) + Send + 'static>(&self, mut handler: F) {
+        //the handler is owned by the source, so the source outlives every call
+        struct SourcePtr(*const Unmanaged);
+        unsafe impl Send for SourcePtr {}
+        impl SourcePtr {
+            fn fired(&self) -> ProcEvents {
+                ProcEvents::from_bits_truncate(unsafe{ dispatch_source_get_data(self.0) } as c_ulong)
+            }
+        }
+        let source = SourcePtr(&**self);
+        (**self).set_event_handler(move || handler(source.fired()))
+    }
+}
+
+///The kind of a source, for the deprecated [Managed::create].
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 pub struct dispatch_source_type_t(*const c_void);
 
 extern "C" {
     static _dispatch_source_type_timer: c_void;
+    static _dispatch_source_type_data_add: c_void;
+    static _dispatch_source_type_data_or: c_void;
+    static _dispatch_source_type_data_replace: c_void;
+    static _dispatch_source_type_read: c_void;
+    static _dispatch_source_type_write: c_void;
+    static _dispatch_source_type_signal: c_void;
+    static _dispatch_source_type_proc: c_void;
     fn dispatch_source_create(tipe: dispatch_source_type_t, handle: uintptr_t, mask: uintptr_t, queue: *const crate::queue::Unmanaged) -> *mut Unmanaged;
     fn dispatch_source_set_event_handler_f(source: *const Unmanaged, handler: extern "C" fn(*mut c_void));
+    ///handler parameter is actually &HandlerBlock
+    fn dispatch_source_set_event_handler(source: *const Unmanaged, handler: *mut c_void);
+    ///handler parameter is actually &HandlerBlock
+    fn dispatch_source_set_cancel_handler(source: *const Unmanaged, handler: *mut c_void);
+    ///handler parameter is actually &HandlerBlock
+    fn dispatch_source_set_registration_handler(source: *const Unmanaged, handler: *mut c_void);
     fn dispatch_source_set_timer(source: *const Unmanaged, time: Time, interval: u64, leeway: u64);
-    fn dispatch_resume(object: *const c_void);
     fn dispatch_source_cancel(source: *const Unmanaged);
-    fn dispatch_suspend(object: *const c_void);
+    fn dispatch_source_testcancel(source: *const Unmanaged) -> isize;
+    fn dispatch_source_get_data(source: *const Unmanaged) -> uintptr_t;
+    fn dispatch_source_get_handle(source: *const Unmanaged) -> uintptr_t;
+    fn dispatch_source_get_mask(source: *const Unmanaged) -> uintptr_t;
+    fn dispatch_source_merge_data(source: *const Unmanaged, value: uintptr_t);
 }
 
 impl dispatch_source_type_t {
@@ -91,6 +604,41 @@ impl dispatch_source_type_t {
             dispatch_source_type_t(&_dispatch_source_type_timer)
         }
     }
+    fn data_add() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_data_add)
+        }
+    }
+    fn data_or() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_data_or)
+        }
+    }
+    fn data_replace() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_data_replace)
+        }
+    }
+    fn read() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_read)
+        }
+    }
+    fn write() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_write)
+        }
+    }
+    fn signal() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_signal)
+        }
+    }
+    fn proc() -> dispatch_source_type_t {
+        unsafe {
+            dispatch_source_type_t(&_dispatch_source_type_proc)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,25 +647,228 @@ mod tests {
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::time::{SystemTime};
     use crate::QoS;
-    use crate::source::{dispatch_source_type_t, Managed};
+    use crate::object::{Object, context_from_raw};
+    use std::time::Duration;
+    use crate::source::{TimerSource, DataAddSource, DataOrSource, DataReplaceSource, ReadSource, WriteSource, SignalSource, ProcSource, ProcEvents};
     use crate::time::Time;
 
     #[test] fn timer() {
         let queue = crate::queue::global(QoS::Default).unwrap();
-        let f = Managed::create(dispatch_source_type_t::timer(), 0, 0, &queue);
-        static ARRIVED: AtomicBool = AtomicBool::new(false);
-        extern "C" fn handler(_arg: *mut c_void) {
-            ARRIVED.store(true, Ordering::Relaxed);
+        let f = TimerSource::new(queue);
+        f.set_context(Box::new(AtomicBool::new(false)));
+        extern "C" fn handler(context: *mut c_void) {
+            let arrived: &AtomicBool = unsafe{ context_from_raw(context) }.unwrap();
+            arrived.store(true, Ordering::Relaxed);
         }
         f.set_event_handler_f(handler);
-        f.set_timer(Time::NOW, 1,1_000_000);
-        f.resume();
+        f.set_timer(Time::NOW, Some(Duration::from_nanos(1)), Duration::from_millis(1));
+        let f = f.activate();
         let started = SystemTime::now();
-        while ARRIVED.load(Ordering::Relaxed) == false {
+        let arrived: &AtomicBool = unsafe{ f.context() }.unwrap();
+        while arrived.load(Ordering::Relaxed) == false {
             let elapsed = started.elapsed().unwrap();
             if elapsed.as_secs() > 1 {
                 panic!("Never arrived!")
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test] fn handlers() {
+        use std::sync::mpsc::channel;
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        let f = TimerSource::new(queue);
+        let target = crate::queue::Managed::new_serial(std::ffi::CStr::from_bytes_with_nul(b"dispatchr.source.handlers\0").unwrap());
+        f.set_target_queue(&target);
+        let (event_sender, event_receiver) = channel();
+        let mut count = 0;
+        f.set_event_handler(move || {
+            count += 1;
+            let _ = event_sender.send(count);
+        });
+        let (registration_sender, registration_receiver) = channel();
+        f.set_registration_handler(move || registration_sender.send(()).unwrap());
+        let (cancel_sender, cancel_receiver) = channel();
+        f.set_cancel_handler(move || cancel_sender.send(()).unwrap());
+        f.set_timer(Time::NOW, Some(Duration::from_millis(1)), Duration::ZERO);
+        let f = f.activate();
+        assert!(registration_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        assert_eq!(event_receiver.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert_eq!(event_receiver.recv_timeout(Duration::from_secs(1)), Ok(2));
+        f.cancel();
+        assert!(cancel_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        drop(f);
+        //once released, the event handler (and its sender) is dropped; drain any events that fired before the cancel
+        let disconnected = loop {
+            match event_receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(disconnected, std::sync::mpsc::RecvTimeoutError::Disconnected);
+    }
+
+    #[test] fn oneshot_timer() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicUsize;
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        let f = Arc::new(TimerSource::new(queue).activate());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let source = f.clone();
+        let fired = AtomicUsize::new(0);
+        f.set_event_handler(move || {
+            fired.fetch_add(source.fire_count(), Ordering::Relaxed);
+            let _ = sender.send(fired.load(Ordering::Relaxed));
+        });
+        f.set_timer(Time::after(Duration::from_millis(1)), None, Duration::ZERO);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        f.cancel();
+        assert!(f.is_cancelled());
+    }
+
+    #[test] fn data() {
+        use std::sync::Arc;
+        use std::sync::mpsc::channel;
+        let queue = crate::queue::global(QoS::Default).unwrap();
+
+        let add = Arc::new(DataAddSource::new(queue).activate());
+        //suspend so the merges coalesce into one event
+        let suspended = Object::suspend(&**add);
+        let (sender, receiver) = channel();
+        let source = add.clone();
+        add.set_event_handler(move || { let _ = sender.send(source.data()); });
+        add.merge(2);
+        add.merge(3);
+        drop(suspended);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(5));
+        add.cancel();
+
+        let or = Arc::new(DataOrSource::new(queue).activate());
+        let suspended = Object::suspend(&**or);
+        let (sender, receiver) = channel();
+        let source = or.clone();
+        or.set_event_handler(move || { let _ = sender.send(source.data()); });
+        or.merge(0b01);
+        or.merge(0b10);
+        drop(suspended);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(0b11));
+        or.cancel();
+
+        let replace = Arc::new(DataReplaceSource::new(queue).activate());
+        let suspended = Object::suspend(&**replace);
+        let (sender, receiver) = channel();
+        let source = replace.clone();
+        replace.set_event_handler(move || { let _ = sender.send(source.data()); });
+        replace.merge(2);
+        replace.merge(3);
+        drop(suspended);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(3));
+        replace.cancel();
+    }
+
+    #[test] fn fd() {
+        use std::io::Write;
+        use std::os::unix::io::{AsRawFd, RawFd};
+        use std::os::unix::net::UnixStream;
+        use std::sync::Arc;
+        use std::sync::mpsc::{channel, Sender};
+        //reports when it's dropped, so we can see when the source closes it
+        struct Tracked(UnixStream, Sender<()>);
+        impl AsRawFd for Tracked {
+            fn as_raw_fd(&self) -> RawFd { self.0.as_raw_fd() }
+        }
+        impl Drop for Tracked {
+            fn drop(&mut self) { let _ = self.1.send(()); }
+        }
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let (closed_sender, closed_receiver) = channel();
+        let read = Arc::new(ReadSource::new(Tracked(reader, closed_sender), queue).activate());
+        let (sender, receiver) = channel();
+        let source = read.clone();
+        read.set_event_handler(move || {
+            let _ = sender.send(source.bytes_available());
+            //stop monitoring, since we never actually read
+            source.cancel();
+        });
+        let (cancel_sender, cancel_receiver) = channel();
+        read.set_cancel_handler(move || cancel_sender.send(()).unwrap());
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(5));
+        assert!(cancel_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        assert!(closed_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+
+        let fd = writer.as_raw_fd();
+        let write = Arc::new(WriteSource::new(writer, queue).activate());
+        assert_eq!(write.fd(), fd);
+        let (sender, receiver) = channel();
+        let source = write.clone();
+        write.set_event_handler(move || {
+            let _ = sender.send(source.buffer_space());
+            source.cancel();
+        });
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).unwrap() > 0);
+    }
+
+    #[test] fn signal() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicUsize;
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        SignalSource::ignore(libc::SIGUSR2).unwrap();
+        let source = Arc::new(SignalSource::new(libc::SIGUSR2, queue).activate());
+        assert_eq!(source.signal(), libc::SIGUSR2);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handler_source = source.clone();
+        let delivered = AtomicUsize::new(0);
+        source.set_event_handler(move || {
+            let count = handler_source.count();
+            let _ = sender.send(delivered.fetch_add(count, Ordering::Relaxed) + count);
+        });
+        //registration is asynchronous; keep raising until the source observes it
+        let mut total = 0;
+        for _ in 0..100 {
+            unsafe{ libc::raise(libc::SIGUSR2) };
+            if let Ok(t) = receiver.recv_timeout(Duration::from_millis(10)) {
+                total = t;
+                break;
+            }
+        }
+        assert!(total >= 1);
+        source.cancel();
+    }
+
+    #[test] fn proc() {
+        assert!((ProcEvents::EXIT | ProcEvents::FORK).contains(ProcEvents::EXIT));
+        assert!(!ProcEvents::EXIT.contains(ProcEvents::EXIT | ProcEvents::FORK));
+        assert_eq!(ProcEvents::from_bits_truncate(0x80000000 | 0x1).bits(), ProcEvents::EXIT.bits());
+
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        let mut child = std::process::Command::new("sleep").arg("0.2").spawn().unwrap();
+        let source = ProcSource::watch_child(&child, ProcEvents::EXIT | ProcEvents::EXEC, queue);
+        assert_eq!(source.pid(), child.id() as libc::pid_t);
+        assert_eq!(source.events(), ProcEvents::EXIT | ProcEvents::EXEC);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        source.set_event_handler(move |events| { let _ = sender.send(events); });
+        let source = source.activate();
+        let fired = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(fired.contains(ProcEvents::EXIT));
+        source.cancel();
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test] #[allow(deprecated)] fn deprecated_create() {
+        use crate::source::{dispatch_source_type_t, Managed};
+        let queue = crate::queue::global(QoS::Default).unwrap();
+        let f = Managed::create(dispatch_source_type_t::timer(), 0, 0, queue);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        f.set_event_handler(move || { let _ = sender.send(()); });
+        f.set_timer(Time::NOW, 1_000_000, 0);
+        f.resume();
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        //balanced raw suspend and resume
+        f.suspend();
+        f.resume();
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        f.cancel();
+    }
+}
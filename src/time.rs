@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::os::raw::{c_ulonglong};
+use std::time::Duration;
 
 extern "C" {
     fn dispatch_time(when: Time, delta: i64) -> Time;
@@ -20,4 +21,21 @@ impl Time {
         unsafe { dispatch_time(self, delta)}
     }
 
-}
\ No newline at end of file
+    ///A deadline `duration` from now.  This reads the clock, so each call returns a different deadline.
+    ///
+    /// Durations too long to represent saturate to a very distant deadline.
+    pub fn after(duration: Duration) -> Self {
+        let nanos = i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX);
+        Time::NOW.new_after(nanos)
+    }
+}
+
+///Error returned when a wait reaches its deadline before the condition is met.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct TimedOut;
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("timed out")
+    }
+}
+impl std::error::Error for TimedOut {}
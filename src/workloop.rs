@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/*! <dispatch/workloop.h>
+
+A workloop is a serial queue that executes its work in priority order, rather than FIFO order.  [Workloop] derefs
+to [crate::queue::Unmanaged], so all the usual submission functions work on it.
+
+Workloops don't support queue-specific data, so [crate::queue::Unmanaged::is_current] always returns `false` for them.
+*/
+use std::ffi::CStr;
+use std::ops::Deref;
+use std::os::raw::{c_char, c_ulong};
+use crate::queue::{AutoreleaseFrequency, Managed, Unmanaged};
+
+extern "C" {
+    fn dispatch_workloop_create(label: *const c_char) -> *const Unmanaged;
+    fn dispatch_workloop_create_inactive(label: *const c_char) -> *const Unmanaged;
+    fn dispatch_workloop_set_autorelease_frequency(workloop: *const Unmanaged, frequency: c_ulong);
+}
+
+/**
+Memory-managed `dispatch_workloop_t`.
+
+Cloning the workloop retains it, and dropping it releases it.
+*/
+#[derive(Debug,Clone)]
+pub struct Workloop(Managed);
+impl Workloop {
+    ///Calls `dispatch_workloop_create`.
+    pub fn new(label: &CStr) -> Self {
+        Workloop(unsafe{ Managed::assume_retained(dispatch_workloop_create(label.as_ptr())) })
+    }
+}
+impl Deref for Workloop {
+    type Target = Unmanaged;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/**
+A workloop that was created inactive, and can still be configured, such as with [Inactive::set_autorelease_frequency].
+See [crate::object::Inactive].
+*/
+pub type Inactive = crate::object::Inactive<Workloop>;
+impl Inactive {
+    ///Calls `dispatch_workloop_create_inactive`.
+    pub fn new(label: &CStr) -> Self {
+        Inactive::from_inactive(Workloop(unsafe{ Managed::assume_retained(dispatch_workloop_create_inactive(label.as_ptr())) }))
+    }
+    ///Calls `dispatch_workloop_set_autorelease_frequency`.
+    pub fn set_autorelease_frequency(&self, frequency: AutoreleaseFrequency) {
+        unsafe{ dispatch_workloop_set_autorelease_frequency(&***self, frequency.as_raw()) }
+    }
+}
+
+#[test] fn workloop() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.workloop\0").unwrap();
+    let workloop = Workloop::new(label);
+    let (sender,receiver) = std::sync::mpsc::channel();
+    workloop.async_closure(move || {
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+}
+
+#[test] fn inactive_workloop() {
+    let label = CStr::from_bytes_with_nul(b"dispatchr.inactive_workloop\0").unwrap();
+    let inactive = Inactive::new(label);
+    inactive.set_autorelease_frequency(AutoreleaseFrequency::WorkItem);
+    let (sender,receiver) = std::sync::mpsc::channel();
+    inactive.async_closure(move || {
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+    let workloop = inactive.activate();
+    assert!(receiver.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+    assert_eq!(workloop.label(), label);
+    assert!(!workloop.is_current());
+}
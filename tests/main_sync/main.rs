@@ -1,8 +1,8 @@
 use dispatchr::queue::DispatchSyncBlock;
-extern "C" {
-    fn dispatch_main();
-}
-fn child_task() {
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+fn child_task(done: Arc<AtomicBool>) {
     use core::pin::Pin;
     let queue = dispatchr::queue::main();
     use std::mem::MaybeUninit;
@@ -12,16 +12,18 @@ fn child_task() {
     let block_value = unsafe{DispatchSyncBlock::new(block_value, || {
         println!("hello from child_task");
         run = true;
+        //set from the main queue, so run_main_until sees it after this drain
+        done.store(true, Ordering::Release);
     }) };
     queue.sync(&block_value);
     assert!(run);
-    std::process::exit(0);
 }
 fn main() {
-    std::thread::spawn(|| {
-         child_task();
+    let done = Arc::new(AtomicBool::new(false));
+    let child_done = done.clone();
+    let child = std::thread::spawn(move || {
+         child_task(child_done);
     });
-    unsafe {
-        dispatch_main()
-    }
+    dispatchr::queue::run_main_until(|| done.load(Ordering::Acquire));
+    child.join().unwrap();
 }